@@ -0,0 +1,127 @@
+//! Hammers a warm client from many tasks at once, reporting the throughput of cache hits,
+//! checks that every expiry under contention leads to exactly one authentication, and reports
+//! how long slow authentications of many keys take
+//!
+//! That keys authenticate concurrently is asserted deterministically by the manager's tests.
+//!
+//! Run with `cargo run --release --example contention`.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use existing_code_challenge::{
+    ApiSecret, AtomicMetrics, AuthConfig, AuthOutput, Authenticator, ClientManager, Clock,
+    ManagerConfig, MockClock, StaticAuthenticator,
+};
+use tokio::task::JoinSet;
+
+const TASKS: usize = 64;
+const HITS_PER_TASK: usize = 10_000;
+const EXPIRIES: i64 = 50;
+const LIFETIME: i64 = 3600;
+const KEYS: usize = 50;
+const AUTH_LATENCY: Duration = Duration::from_millis(500);
+
+/// Takes a while to answer, like a backend on the other side of the world
+struct SlowAuthenticator;
+
+#[async_trait]
+impl Authenticator for SlowAuthenticator {
+    async fn authenticate(
+        &self,
+        _config: &AuthConfig,
+        api_key: &str,
+        _api_secret: &ApiSecret,
+    ) -> anyhow::Result<AuthOutput> {
+        tokio::time::sleep(AUTH_LATENCY).await;
+
+        Ok(AuthOutput::new(format!("token-{api_key}"), LIFETIME))
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let clock = MockClock::new(0);
+    let metrics = Arc::new(AtomicMetrics::new());
+    let manager = ClientManager::with_parts(
+        ManagerConfig::default(),
+        Arc::new(StaticAuthenticator),
+        Arc::new(clock.clone()),
+        metrics.clone(),
+    );
+    manager.get_client("api-key", "secret").await.unwrap();
+
+    let start = Instant::now();
+    let mut tasks = JoinSet::new();
+    for _ in 0..TASKS {
+        let manager = manager.clone();
+        tasks.spawn(async move {
+            for _ in 0..HITS_PER_TASK {
+                manager.get_client("api-key", "secret").await.unwrap();
+            }
+        });
+    }
+    while let Some(res) = tasks.join_next().await {
+        res.unwrap();
+    }
+    let elapsed = start.elapsed();
+    let hits = TASKS * HITS_PER_TASK;
+    println!(
+        "{hits} hits from {TASKS} tasks in {elapsed:?}, {:.0} hits/s",
+        hits as f64 / elapsed.as_secs_f64()
+    );
+
+    // Every time the token expires all tasks race to refresh it, but only one may authenticate
+    for expiry in 1..=EXPIRIES {
+        clock.advance(LIFETIME);
+
+        let mut tasks = JoinSet::new();
+        for _ in 0..TASKS {
+            let manager = manager.clone();
+            let clock = clock.clone();
+            tasks.spawn(async move {
+                let token = manager.get_token("api-key", "secret").await.unwrap();
+                assert!(token.expires_at() > clock.now(), "served an expired token");
+            });
+        }
+        while let Some(res) = tasks.join_next().await {
+            res.unwrap();
+        }
+
+        assert_eq!(metrics.auth_successes(), 1 + expiry as u64);
+    }
+    println!("{EXPIRIES} expiries under contention, one authentication each");
+
+    // Keys don't wait for each other's authentication, only for their own. Building the clients
+    // still takes some CPU time of its own, so this is far from the latency of a single one.
+    let metrics = Arc::new(AtomicMetrics::new());
+    let manager = ClientManager::builder()
+        .authenticator(Arc::new(SlowAuthenticator))
+        .metrics(metrics.clone())
+        .build();
+
+    let start = Instant::now();
+    let mut tasks = JoinSet::new();
+    for key in 0..KEYS {
+        for _ in 0..TASKS / 8 {
+            let manager = manager.clone();
+            tasks.spawn(async move {
+                manager
+                    .get_client(format!("key-{key}"), "secret")
+                    .await
+                    .unwrap();
+            });
+        }
+    }
+    while let Some(res) = tasks.join_next().await {
+        res.unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    assert_eq!(metrics.auth_successes(), KEYS as u64);
+    let sequential = AUTH_LATENCY * KEYS as u32;
+    println!(
+        "{KEYS} keys authenticated concurrently in {elapsed:?}, {sequential:?} one after another"
+    );
+}
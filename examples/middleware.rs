@@ -0,0 +1,94 @@
+//! Authorizes the requests of a `reqwest-middleware` client with an `AuthMiddleware`, against a
+//! local server that rejects the first token it sees, checking that the request is replayed
+//! with a refreshed one
+//!
+//! Run with `cargo run --example middleware --features middleware`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use existing_code_challenge::{
+    ApiSecret, AuthConfig, AuthMiddleware, AuthOutput, Authenticator, ClientManager,
+};
+use reqwest::StatusCode;
+use reqwest_middleware::ClientBuilder;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+/// Mints a distinct token on every call
+#[derive(Default)]
+struct CountingAuthenticator(AtomicUsize);
+
+#[async_trait]
+impl Authenticator for CountingAuthenticator {
+    async fn authenticate(
+        &self,
+        _config: &AuthConfig,
+        _api_key: &str,
+        _api_secret: &ApiSecret,
+    ) -> anyhow::Result<AuthOutput> {
+        let call = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+
+        Ok(AuthOutput::new(format!("token-{call}"), 3600))
+    }
+}
+
+/// Answers `401 Unauthorized` to requests with the first token, as if it had been revoked,
+/// and `200 OK` to all others
+async fn serve(listener: TcpListener, seen: Arc<Mutex<Vec<String>>>) {
+    loop {
+        let (stream, _) = listener.accept().await.unwrap();
+        let seen = seen.clone();
+
+        tokio::spawn(async move {
+            let mut stream = BufReader::new(stream);
+            let mut line = String::new();
+            let mut authorization = String::new();
+            loop {
+                line.clear();
+                if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                    return;
+                }
+                if let Some(value) = line.strip_prefix("authorization: ") {
+                    authorization = value.trim().to_string();
+                }
+                // The blank line ending the headers of a request without a body
+                if line == "\r\n" {
+                    let response: &[u8] = if authorization == "Bearer token-1" {
+                        b"HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\n\r\n"
+                    } else {
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n"
+                    };
+                    seen.lock().unwrap().push(authorization.clone());
+                    stream.get_mut().write_all(response).await.unwrap();
+                }
+            }
+        });
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    tokio::spawn(serve(listener, seen.clone()));
+
+    let manager = ClientManager::builder()
+        .authenticator(Arc::new(CountingAuthenticator::default()))
+        .build();
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(AuthMiddleware::new(manager, "api-key", "secret"))
+        .build();
+
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // The refreshed token is cached for the requests that follow
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let seen = seen.lock().unwrap().clone();
+    println!("authorizations: {seen:?}");
+    assert_eq!(seen, ["Bearer token-1", "Bearer token-2", "Bearer token-2"]);
+}
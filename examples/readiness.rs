@@ -0,0 +1,36 @@
+//! Authenticates all known credentials before reporting the service as ready
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use existing_code_challenge::{ApiKey, ApiSecret, ClientManager};
+
+#[tokio::main]
+async fn main() {
+    let manager = ClientManager::default();
+    let ready = Arc::new(AtomicBool::new(false));
+
+    // Usually read from the service configuration
+    let credentials = [
+        (ApiKey::new("first-api-key"), ApiSecret::new("first-secret")),
+        (
+            ApiKey::new("second-api-key"),
+            ApiSecret::new("second-secret"),
+        ),
+    ];
+
+    let mut failed = false;
+    for (api_key, res) in manager.warm_up(credentials).await {
+        if let Err(err) = res {
+            eprintln!("Failed to authenticate {}: {err}", api_key.fingerprint());
+            failed = true;
+        }
+    }
+
+    // A readiness probe would report this flag, the successful clients are cached either way
+    ready.store(!failed, Ordering::SeqCst);
+    println!("ready: {}", ready.load(Ordering::SeqCst));
+
+    // Requests served afterwards find the clients in the cache
+    assert!(manager.is_cached("first-api-key"));
+}
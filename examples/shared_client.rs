@@ -0,0 +1,115 @@
+//! Sends requests through the single client of `ClientMode::Shared` to a local server, checking
+//! that they keep using one connection even as the token is refreshed
+//!
+//! Run with `cargo run --example shared_client`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use existing_code_challenge::{
+    ApiSecret, AuthConfig, AuthOutput, Authenticator, ClientManager, ClientMode, ManagerConfig,
+    MockClock,
+};
+use reqwest::Method;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+const LIFETIME: i64 = 3600;
+
+/// Mints a distinct token on every call
+#[derive(Default)]
+struct CountingAuthenticator(AtomicUsize);
+
+#[async_trait]
+impl Authenticator for CountingAuthenticator {
+    async fn authenticate(
+        &self,
+        _config: &AuthConfig,
+        _api_key: &str,
+        _api_secret: &ApiSecret,
+    ) -> anyhow::Result<AuthOutput> {
+        let call = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+
+        Ok(AuthOutput::new(format!("token-{call}"), LIFETIME))
+    }
+}
+
+/// What the local server saw
+#[derive(Default)]
+struct Seen {
+    connections: AtomicUsize,
+    authorizations: Mutex<Vec<String>>,
+}
+
+/// Answers every request with `200 OK`, keeping connections open
+async fn serve(listener: TcpListener, seen: Arc<Seen>) {
+    loop {
+        let (stream, _) = listener.accept().await.unwrap();
+        seen.connections.fetch_add(1, Ordering::SeqCst);
+        let seen = seen.clone();
+
+        tokio::spawn(async move {
+            let mut stream = BufReader::new(stream);
+            let mut line = String::new();
+            loop {
+                line.clear();
+                if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                    return;
+                }
+                if let Some(value) = line.strip_prefix("authorization: ") {
+                    seen.authorizations
+                        .lock()
+                        .unwrap()
+                        .push(value.trim().to_string());
+                }
+                // The blank line ending the headers of a request without a body
+                if line == "\r\n" {
+                    let response = b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n";
+                    stream.get_mut().write_all(response).await.unwrap();
+                }
+            }
+        });
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let seen = Arc::new(Seen::default());
+    tokio::spawn(serve(listener, seen.clone()));
+
+    let clock = MockClock::new(0);
+    let manager = ClientManager::builder()
+        .authenticator(Arc::new(CountingAuthenticator::default()))
+        .clock(Arc::new(clock.clone()))
+        .config(ManagerConfig {
+            client_mode: ClientMode::Shared,
+            ..ManagerConfig::default()
+        })
+        .build();
+
+    for _ in 0..3 {
+        let response = manager
+            .authorized_request(Method::GET, &url, "api-key", "secret")
+            .await
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+
+        // The next request needs a new token
+        clock.advance(LIFETIME);
+    }
+
+    let authorizations = seen.authorizations.lock().unwrap().clone();
+    println!("authorizations: {authorizations:?}");
+    println!("connections: {}", seen.connections.load(Ordering::SeqCst));
+    assert_eq!(
+        authorizations,
+        ["Bearer token-1", "Bearer token-2", "Bearer token-3"]
+    );
+    assert_eq!(seen.connections.load(Ordering::SeqCst), 1);
+}
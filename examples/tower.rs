@@ -0,0 +1,100 @@
+//! Stacks an `AuthLayer` over a stub service recording the headers of its requests, checking
+//! that they carry the current token before and after it's refreshed
+//!
+//! Run with `cargo run --example tower --features tower`.
+
+use std::convert::Infallible;
+use std::future::{poll_fn, ready, Ready};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use existing_code_challenge::{
+    ApiSecret, AuthConfig, AuthLayer, AuthOutput, Authenticator, ClientManager, MockClock,
+};
+use reqwest::header::{HeaderMap, AUTHORIZATION};
+use tower::{Layer, Service};
+
+const LIFETIME: i64 = 3600;
+
+/// Mints a distinct token on every call
+#[derive(Default)]
+struct CountingAuthenticator(AtomicUsize);
+
+#[async_trait]
+impl Authenticator for CountingAuthenticator {
+    async fn authenticate(
+        &self,
+        _config: &AuthConfig,
+        _api_key: &str,
+        _api_secret: &ApiSecret,
+    ) -> anyhow::Result<AuthOutput> {
+        let call = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+
+        Ok(AuthOutput::new(format!("token-{call}"), LIFETIME))
+    }
+}
+
+/// Records the headers of every request and answers right away
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<HeaderMap>>>);
+
+impl Service<http::Request<()>> for Recorder {
+    type Response = ();
+    type Error = Infallible;
+    type Future = Ready<Result<(), Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<()>) -> Self::Future {
+        self.0.lock().unwrap().push(request.headers().clone());
+        ready(Ok(()))
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let clock = MockClock::new(0);
+    let manager = ClientManager::builder()
+        .authenticator(Arc::new(CountingAuthenticator::default()))
+        .clock(Arc::new(clock.clone()))
+        .build();
+    let recorder = Recorder::default();
+    let mut service = AuthLayer::new(manager, "api-key", "secret").layer(recorder.clone());
+
+    for _ in 0..2 {
+        for _ in 0..2 {
+            poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+            service.call(http::Request::new(())).await.unwrap();
+        }
+
+        // The next requests need a new token
+        clock.advance(LIFETIME);
+    }
+
+    let seen: Vec<_> = recorder
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|headers| {
+            let authorization = headers[AUTHORIZATION].to_str().unwrap().to_string();
+            assert!(headers[AUTHORIZATION].is_sensitive());
+            assert_eq!(headers["x-api-key"], "api-key");
+            authorization
+        })
+        .collect();
+    println!("authorizations: {seen:?}");
+    assert_eq!(
+        seen,
+        [
+            "Bearer token-1",
+            "Bearer token-1",
+            "Bearer token-2",
+            "Bearer token-2"
+        ]
+    );
+}
@@ -0,0 +1,200 @@
+//! Authentication backends
+
+use std::fmt;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::{ApiSecret, AuthConfig, ErrorKind};
+
+/// A successful authentication
+pub struct AuthOutput {
+    access_token: String,
+    expires_in: i64,
+    refresh_token: Option<String>,
+    refresh_expires_in: Option<i64>,
+    scope: Option<Vec<String>>,
+}
+
+impl fmt::Debug for AuthOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthOutput")
+            .field("expires_in", &self.expires_in)
+            .field("has_refresh_token", &self.refresh_token.is_some())
+            .field("refresh_expires_in", &self.refresh_expires_in)
+            .field("scope", &self.scope)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AuthOutput {
+    pub fn new(access_token: impl Into<String>, expires_in: i64) -> Self {
+        Self {
+            access_token: access_token.into(),
+            expires_in,
+            refresh_token: None,
+            refresh_expires_in: None,
+            scope: None,
+        }
+    }
+
+    /// Attaches a refresh token that can later be exchanged for a new access token
+    pub fn with_refresh_token(mut self, refresh_token: impl Into<String>) -> Self {
+        self.refresh_token = Some(refresh_token.into());
+        self
+    }
+
+    /// Sets in how many seconds the refresh token expires, after which it's no longer tried
+    ///
+    /// Without it the refresh token is tried until the backend refuses it.
+    pub fn with_refresh_expires_in(mut self, expires_in: i64) -> Self {
+        self.refresh_expires_in = Some(expires_in);
+        self
+    }
+
+    /// Sets the scopes the token was granted, if the backend reports them
+    ///
+    /// Without them the token is assumed to have been granted exactly the requested scopes.
+    pub fn with_scope(mut self, scope: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.scope = Some(scope.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn access_token(&self) -> &str {
+        &self.access_token
+    }
+
+    pub fn expires_in(&self) -> i64 {
+        self.expires_in
+    }
+
+    pub fn refresh_token(&self) -> Option<&str> {
+        self.refresh_token.as_deref()
+    }
+
+    pub fn refresh_expires_in(&self) -> Option<i64> {
+        self.refresh_expires_in
+    }
+
+    pub fn scope(&self) -> Option<&[String]> {
+        self.scope.as_deref()
+    }
+}
+
+/// A backend capable of exchanging an api key and secret for an access token
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    async fn authenticate(
+        &self,
+        config: &AuthConfig,
+        api_key: &str,
+        api_secret: &ApiSecret,
+    ) -> anyhow::Result<AuthOutput>;
+
+    /// Like [`authenticate`](Self::authenticate), but for a token covering at least `scopes`,
+    /// which are sorted and never empty
+    ///
+    /// Backends issuing scoped tokens should report the scopes actually granted with
+    /// [`AuthOutput::with_scope`]. The default ignores the scopes and authenticates as usual.
+    async fn authenticate_scoped(
+        &self,
+        config: &AuthConfig,
+        api_key: &str,
+        api_secret: &ApiSecret,
+        _scopes: &[String],
+    ) -> anyhow::Result<AuthOutput> {
+        self.authenticate(config, api_key, api_secret).await
+    }
+
+    /// Exchanges a refresh token from a previous [`AuthOutput`] for a new access token
+    ///
+    /// This is preferred over a full authentication whenever a refresh token is available and not
+    /// [expired](AuthOutput::with_refresh_expires_in), so that the secret is sent less often,
+    /// falling back to [`authenticate`](Self::authenticate) if it fails.
+    /// Backends without refresh tokens can rely on the default, which always fails.
+    async fn refresh(
+        &self,
+        _config: &AuthConfig,
+        _api_key: &str,
+        _refresh_token: &str,
+    ) -> anyhow::Result<AuthOutput> {
+        anyhow::bail!("Refresh tokens are not supported")
+    }
+
+    /// Whether a failed authentication is worth retrying, e.g. a throttled or timed out request
+    ///
+    /// Rejected credentials must never be classified as retryable. By default only
+    /// `reqwest` timeouts, connection errors and 5xx responses found in the error chain are retried.
+    fn is_retryable(&self, err: &anyhow::Error) -> bool {
+        is_transient(err)
+    }
+
+    /// What a failed authentication is reported as, in [`Error::kind`](crate::Error::kind)
+    ///
+    /// Only [`ErrorKind::Transient`] failures are retried and count towards the circuit breaker.
+    /// The default classifies [retryable](Self::is_retryable) failures as transient and all
+    /// others as rejected, backends can tell apart e.g. a malformed response as
+    /// [`ErrorKind::Invalid`].
+    fn classify(&self, err: &anyhow::Error) -> ErrorKind {
+        if self.is_retryable(err) {
+            ErrorKind::Transient
+        } else {
+            ErrorKind::Rejected
+        }
+    }
+
+    /// How long the backend asked to wait before retrying a failed authentication, e.g. with
+    /// the `Retry-After` header of a `429 Too Many Requests` response
+    ///
+    /// Backends with typed errors carrying the response can report it, see
+    /// [`parse_retry_after`](crate::parse_retry_after). The default never knows.
+    fn retry_after(&self, _err: &anyhow::Error) -> Option<Duration> {
+        None
+    }
+}
+
+/// Whether `err` was caused by a network problem or a server error rather than the request itself
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        .any(is_transient_request)
+}
+
+pub(crate) fn is_transient_request(err: &reqwest::Error) -> bool {
+    err.is_timeout()
+        || err.is_connect()
+        || err.status().is_some_and(|status| status.is_server_error())
+}
+
+/// The placeholder Cognito authentication against the pool described by the [`AuthConfig`]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StaticAuthenticator;
+
+#[async_trait]
+impl Authenticator for StaticAuthenticator {
+    async fn authenticate(
+        &self,
+        config: &AuthConfig,
+        api_key: &str,
+        api_secret: &ApiSecret,
+    ) -> anyhow::Result<AuthOutput> {
+        authenticate(config, api_key, api_secret).await
+    }
+}
+
+/// A placeholder auth implementation
+async fn authenticate(
+    config: &AuthConfig,
+    api_key: &str,
+    _password: &ApiSecret,
+) -> anyhow::Result<AuthOutput> {
+    let AuthConfig { client_id, pool_id } = config;
+
+    Ok(AuthOutput {
+        access_token: format!("{client_id}:{pool_id}:{api_key}"),
+        expires_in: 3600,
+        refresh_token: None,
+        refresh_expires_in: None,
+        scope: None,
+    })
+}
@@ -0,0 +1,174 @@
+use reqwest::header::HeaderMap;
+use reqwest::{Body, Client, IntoUrl, Request, Response};
+
+use crate::{ApiKey, ApiSecret, ClientManager, Error};
+
+/// A client for a single api key that re-authenticates and replays a request if it's answered
+/// with `401 Unauthorized` or `403 Forbidden`, e.g. because the token was revoked before it expired
+///
+/// How often and for which statuses is configured by the manager's [`ReplayPolicy`](crate::ReplayPolicy).
+/// Only idempotent requests are replayed unless [`replay_non_idempotent`](Self::replay_non_idempotent)
+/// is set, and only if their body can be cloned, i.e. isn't a stream.
+/// Otherwise the response is returned as is, as is the response to the last replay.
+#[derive(Debug, Clone)]
+pub struct AuthedClient {
+    manager: ClientManager,
+    api_key: ApiKey,
+    api_secret: ApiSecret,
+    replay_non_idempotent: bool,
+}
+
+impl AuthedClient {
+    pub fn new(
+        manager: ClientManager,
+        api_key: impl Into<ApiKey>,
+        api_secret: impl Into<ApiSecret>,
+    ) -> Self {
+        Self {
+            manager,
+            api_key: api_key.into(),
+            api_secret: api_secret.into(),
+            replay_non_idempotent: false,
+        }
+    }
+
+    /// Whether requests like `POST` may be replayed as well, which could apply them twice
+    pub fn replay_non_idempotent(mut self, replay: bool) -> Self {
+        self.replay_non_idempotent = replay;
+        self
+    }
+
+    pub async fn get(&self, url: impl IntoUrl) -> Result<Response, Error> {
+        let authorized = self.authorize(false).await?;
+        let request = authorized.0.get(url).build().map_err(Error::request)?;
+
+        self.send(authorized, request).await
+    }
+
+    pub async fn post(&self, url: impl IntoUrl, body: impl Into<Body>) -> Result<Response, Error> {
+        let authorized = self.authorize(false).await?;
+        let request = authorized
+            .0
+            .post(url)
+            .body(body)
+            .build()
+            .map_err(Error::request)?;
+
+        self.send(authorized, request).await
+    }
+
+    pub async fn execute(&self, request: Request) -> Result<Response, Error> {
+        let authorized = self.authorize(false).await?;
+
+        self.send(authorized, request).await
+    }
+
+    async fn authorize(&self, force: bool) -> Result<(Client, HeaderMap), Error> {
+        self.manager
+            .authorize(&self.api_key, &self.api_secret, force)
+            .await
+    }
+
+    async fn send(
+        &self,
+        (mut client, mut headers): (Client, HeaderMap),
+        mut request: Request,
+    ) -> Result<Response, Error> {
+        let policy = &self.manager.config().replay;
+        let replayable = self.replay_non_idempotent || request.method().is_idempotent();
+
+        let mut replays = 0;
+        loop {
+            // Replaces the headers of a rejected token on replays, in `ClientMode::Shared`
+            request.headers_mut().extend(headers);
+            let replay = if replayable && replays < policy.max_replays {
+                request.try_clone()
+            } else {
+                None
+            };
+
+            let response = client.execute(request).await.map_err(Error::request)?;
+            if !policy.statuses.contains(&response.status()) {
+                return Ok(response);
+            }
+            let Some(replay) = replay else {
+                return Ok(response);
+            };
+
+            (client, headers) = self.authorize(true).await?;
+            request = replay;
+            replays += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use reqwest::StatusCode;
+
+    use super::*;
+    use crate::test_server::TestServer;
+    use crate::MockAuthenticator;
+
+    fn client(mock: &MockAuthenticator) -> AuthedClient {
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .build();
+
+        AuthedClient::new(manager, "key", "secret")
+    }
+
+    #[tokio::test]
+    async fn replays_after_unauthorized() {
+        let server = TestServer::start([401]).await;
+        let mock = MockAuthenticator::default();
+        let client = client(&mock);
+
+        let response = client.get(&server.url).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            server.authorizations(),
+            ["Bearer mock:key:1", "Bearer mock:key:2"]
+        );
+        mock.assert_calls(2);
+
+        // The refreshed token is cached for the requests that follow
+        client.get(&server.url).await.unwrap();
+        assert_eq!(server.authorizations()[2], "Bearer mock:key:2");
+        mock.assert_calls(2);
+    }
+
+    #[tokio::test]
+    async fn replays_only_once() {
+        let server = TestServer::start([401, 401, 401]).await;
+        let mock = MockAuthenticator::default();
+
+        let response = client(&mock).get(&server.url).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(server.authorizations().len(), 2);
+        mock.assert_calls(2);
+    }
+
+    #[tokio::test]
+    async fn non_idempotent_requests_are_not_replayed() {
+        let server = TestServer::start([401]).await;
+        let mock = MockAuthenticator::default();
+
+        let response = client(&mock).post(&server.url, "body").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(server.authorizations().len(), 1);
+
+        let server = TestServer::start([401]).await;
+        let response = client(&mock)
+            .replay_non_idempotent(true)
+            .post(&server.url, "body")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(server.authorizations().len(), 2);
+    }
+}
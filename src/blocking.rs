@@ -0,0 +1,125 @@
+//! Functions for callers without an async runtime, available with the `blocking` feature
+
+use once_cell::sync::Lazy;
+use reqwest::Client;
+use tokio::runtime::{Handle, Runtime};
+
+use crate::headers::auth_headers;
+use crate::{AccessToken, ApiKey, ApiSecret, Error, DEFAULT_MANAGER};
+
+/// Drives the authentications of blocking callers, spawned on first use
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("client-manager-blocking")
+        .enable_all()
+        .build()
+        .expect("Failed to start the runtime for blocking callers")
+});
+
+/// Blocking version of [`refresh_client`](crate::refresh_client), sharing its cache
+///
+/// Runs the authentication on a dedicated runtime, so it can be called without one.
+///
+/// Fails with [`Error::InsideRuntime`] when called from within an async runtime, where it
+/// would block a worker thread, use [`refresh_client`](crate::refresh_client) there instead.
+pub fn refresh_client_blocking(
+    api_key: impl Into<ApiKey>,
+    api_secret: impl Into<ApiSecret>,
+) -> Result<Client, Error> {
+    if Handle::try_current().is_ok() {
+        return Err(Error::InsideRuntime);
+    }
+
+    let (api_key, api_secret) = (api_key.into(), api_secret.into());
+    RUNTIME.block_on(DEFAULT_MANAGER.get_client(api_key, api_secret))
+}
+
+/// Returns a [`reqwest::blocking::Client`] for `api_key`, sharing the token cache of
+/// [`refresh_client`](crate::refresh_client)
+///
+/// The blocking client is cached along with the token and rebuilt only once the token is
+/// replaced, so it's evicted and invalidated with it. It's configured like the clients of the
+/// default manager, except for the [`builder_hook`](crate::ClientConfig::builder_hook).
+///
+/// Fails with [`Error::InsideRuntime`] when called from within an async runtime, where blocking
+/// clients can't be used.
+pub fn refresh_client(
+    api_key: impl Into<ApiKey>,
+    api_secret: impl Into<ApiSecret>,
+) -> Result<reqwest::blocking::Client, Error> {
+    if Handle::try_current().is_ok() {
+        return Err(Error::InsideRuntime);
+    }
+
+    let api_key = api_key.into();
+    let (token, slot) =
+        RUNTIME.block_on(DEFAULT_MANAGER.blocking_slot(api_key.clone(), api_secret.into()))?;
+
+    let build = || build_client(&token, &api_key);
+    match slot {
+        Some(slot) => slot.get_or_try_init(build).cloned(),
+        // Not cached, e.g. because it's expired already
+        None => build(),
+    }
+}
+
+fn build_client(token: &AccessToken, api_key: &ApiKey) -> Result<reqwest::blocking::Client, Error> {
+    let config = DEFAULT_MANAGER.config();
+    let headers = auth_headers(
+        config,
+        token.as_str(),
+        api_key.as_str(),
+        config.extra_headers.clone(),
+    )?;
+
+    config
+        .client
+        .apply_blocking(reqwest::blocking::Client::builder())
+        .and_then(|builder| builder.default_headers(headers).build())
+        .map_err(Error::client_build)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocking_client_cached(api_key: &str) -> bool {
+        let (_, slot) = RUNTIME
+            .block_on(DEFAULT_MANAGER.blocking_slot(api_key.into(), "secret".into()))
+            .unwrap();
+
+        slot.is_some_and(|slot| slot.get().is_some())
+    }
+
+    #[test]
+    fn refresh_client_without_runtime() {
+        refresh_client("blocking-standalone", "secret").unwrap();
+        assert!(blocking_client_cached("blocking-standalone"));
+
+        // Dropped along with the cached token
+        RUNTIME.block_on(DEFAULT_MANAGER.invalidate("blocking-standalone"));
+        assert!(!blocking_client_cached("blocking-standalone"));
+        refresh_client("blocking-standalone", "secret").unwrap();
+        assert!(blocking_client_cached("blocking-standalone"));
+    }
+
+    #[test]
+    fn refresh_client_blocking_without_runtime() {
+        refresh_client_blocking("blocking-async-standalone", "secret").unwrap();
+    }
+
+    #[tokio::test]
+    async fn refresh_client_blocking_inside_runtime() {
+        let res = refresh_client_blocking("blocking-async-inside-runtime", "secret");
+
+        assert!(matches!(res, Err(Error::InsideRuntime)));
+    }
+
+    #[tokio::test]
+    async fn refresh_client_inside_runtime() {
+        let res = refresh_client("blocking-inside-runtime", "secret");
+
+        assert!(matches!(res, Err(Error::InsideRuntime)));
+    }
+}
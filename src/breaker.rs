@@ -0,0 +1,244 @@
+use std::sync::Mutex;
+
+use crate::CircuitBreakerConfig;
+
+/// The state of a manager's circuit breaker, see [`CircuitBreakerConfig`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Authentications reach the backend
+    Closed,
+    /// Authentications fail right away until the unix timestamp `until`
+    Open { until: i64 },
+    /// The cooldown is over, the next authentication probes whether the backend recovered
+    /// while the others keep failing right away
+    HalfOpen,
+}
+
+/// Stops authenticating for a while after the backend failed repeatedly
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug)]
+struct BreakerState {
+    circuit: Circuit,
+    /// Consecutive failures since `first_failure`
+    failures: u32,
+    first_failure: i64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Circuit {
+    Closed,
+    Open {
+        until: i64,
+    },
+    /// A probe started at `since`, which is given up on after another cooldown
+    Probing {
+        since: i64,
+    },
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(BreakerState {
+                circuit: Circuit::Closed,
+                failures: 0,
+                first_failure: 0,
+            }),
+        }
+    }
+
+    pub fn state(&self, now: i64) -> CircuitState {
+        match self.state.lock().unwrap().circuit {
+            Circuit::Closed => CircuitState::Closed,
+            Circuit::Open { until } if now < until => CircuitState::Open { until },
+            Circuit::Open { .. } | Circuit::Probing { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Whether an authentication may reach the backend at `now`, making it the probe
+    /// if the cooldown is over
+    pub fn admit(&self, now: i64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.circuit {
+            Circuit::Closed => true,
+            Circuit::Open { until } if now < until => false,
+            // A probe that never reported back, e.g. because it was cancelled, is replaced
+            Circuit::Probing { since } if now - since < self.config.cooldown => false,
+            Circuit::Open { .. } | Circuit::Probing { .. } => {
+                state.circuit = Circuit::Probing { since: now };
+                true
+            }
+        }
+    }
+
+    pub fn on_success(&self) {
+        self.reset();
+    }
+
+    pub fn on_failure(&self, now: i64) {
+        let mut state = self.state.lock().unwrap();
+        if let Circuit::Probing { .. } = state.circuit {
+            state.circuit = Circuit::Open {
+                until: now + self.config.cooldown,
+            };
+            return;
+        }
+
+        if state.failures == 0 || now - state.first_failure > self.config.window {
+            state.failures = 0;
+            state.first_failure = now;
+        }
+        state.failures += 1;
+
+        if state.failures >= self.config.failure_threshold {
+            state.circuit = Circuit::Open {
+                until: now + self.config.cooldown,
+            };
+            event!(
+                WARN,
+                failures = state.failures,
+                "Opened the circuit breaker"
+            );
+        }
+    }
+
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.circuit = Circuit::Closed;
+        state.failures = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Clock, MockClock};
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 3,
+            window: 60,
+            cooldown: 30,
+        })
+    }
+
+    /// Opens the circuit of `breaker` at the time of `clock`
+    fn open(breaker: &CircuitBreaker, clock: &MockClock) {
+        for _ in 0..3 {
+            assert!(breaker.admit(clock.now()));
+            breaker.on_failure(clock.now());
+        }
+    }
+
+    #[test]
+    fn opens_after_threshold_failures() {
+        let clock = MockClock::new(1_000);
+        let breaker = breaker();
+
+        breaker.on_failure(clock.now());
+        breaker.on_failure(clock.now());
+        assert_eq!(breaker.state(clock.now()), CircuitState::Closed);
+        assert!(breaker.admit(clock.now()));
+
+        breaker.on_failure(clock.now());
+        assert_eq!(
+            breaker.state(clock.now()),
+            CircuitState::Open { until: 1_030 }
+        );
+        assert!(!breaker.admit(clock.now()));
+    }
+
+    #[test]
+    fn failures_outside_the_window_start_over() {
+        let clock = MockClock::new(1_000);
+        let breaker = breaker();
+
+        breaker.on_failure(clock.now());
+        breaker.on_failure(clock.now());
+        clock.advance(61);
+        breaker.on_failure(clock.now());
+
+        assert_eq!(breaker.state(clock.now()), CircuitState::Closed);
+    }
+
+    #[test]
+    fn success_resets_the_failures() {
+        let clock = MockClock::new(1_000);
+        let breaker = breaker();
+
+        breaker.on_failure(clock.now());
+        breaker.on_failure(clock.now());
+        breaker.on_success();
+        breaker.on_failure(clock.now());
+
+        assert_eq!(breaker.state(clock.now()), CircuitState::Closed);
+    }
+
+    #[test]
+    fn admits_a_single_probe_after_the_cooldown() {
+        let clock = MockClock::new(1_000);
+        let breaker = breaker();
+        open(&breaker, &clock);
+
+        clock.advance(29);
+        assert!(!breaker.admit(clock.now()));
+
+        clock.advance(1);
+        assert_eq!(breaker.state(clock.now()), CircuitState::HalfOpen);
+        assert!(breaker.admit(clock.now()));
+        assert!(!breaker.admit(clock.now()));
+        assert_eq!(breaker.state(clock.now()), CircuitState::HalfOpen);
+    }
+
+    #[test]
+    fn successful_probe_closes_the_circuit() {
+        let clock = MockClock::new(1_000);
+        let breaker = breaker();
+        open(&breaker, &clock);
+
+        clock.advance(30);
+        assert!(breaker.admit(clock.now()));
+        breaker.on_success();
+
+        assert_eq!(breaker.state(clock.now()), CircuitState::Closed);
+        assert!(breaker.admit(clock.now()));
+    }
+
+    #[test]
+    fn failed_probe_opens_the_circuit_again() {
+        let clock = MockClock::new(1_000);
+        let breaker = breaker();
+        open(&breaker, &clock);
+
+        clock.advance(30);
+        assert!(breaker.admit(clock.now()));
+        breaker.on_failure(clock.now());
+
+        assert_eq!(
+            breaker.state(clock.now()),
+            CircuitState::Open { until: 1_060 }
+        );
+        assert!(!breaker.admit(clock.now()));
+    }
+
+    #[test]
+    fn abandoned_probe_is_replaced_after_another_cooldown() {
+        let clock = MockClock::new(1_000);
+        let breaker = breaker();
+        open(&breaker, &clock);
+
+        clock.advance(30);
+        assert!(breaker.admit(clock.now()));
+
+        clock.advance(29);
+        assert!(!breaker.admit(clock.now()));
+        clock.advance(1);
+        assert!(breaker.admit(clock.now()));
+    }
+}
@@ -0,0 +1,150 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    Authenticator, ClientManager, Clock, CredentialsProvider, KeyDeriver, ManagerConfig, Metrics,
+    NoopMetrics, RetryPolicy, StaticAuthenticator, SystemClock, TokenStore,
+};
+
+/// Configures a [`ClientManager`], starting from the default [`ManagerConfig`]
+///
+/// Setters for the most common options are provided, anything else can be set on the whole
+/// [`config`](Self::config). Parts that aren't set default to the [`StaticAuthenticator`], the
+/// [`SystemClock`], [`NoopMetrics`], no token store and one client per api key.
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// use existing_code_challenge::{AtomicMetrics, ClientManager, RetryPolicy};
+///
+/// let metrics = Arc::new(AtomicMetrics::new());
+/// let manager = ClientManager::builder()
+///     .refresh_skew(120)
+///     .max_capacity(1_000)
+///     .request_timeout(Duration::from_secs(10))
+///     .retry_policy(RetryPolicy {
+///         max_attempts: 5,
+///         ..RetryPolicy::default()
+///     })
+///     .metrics(metrics.clone())
+///     .build();
+///
+/// assert_eq!(manager.capacity(), Some(1_000));
+/// assert_eq!(manager.config().refresh_skew, 120);
+/// ```
+#[must_use]
+pub struct ClientManagerBuilder {
+    config: ManagerConfig,
+    authenticator: Arc<dyn Authenticator>,
+    clock: Arc<dyn Clock>,
+    metrics: Arc<dyn Metrics>,
+    store: Option<Arc<dyn TokenStore>>,
+    key_deriver: Option<Arc<dyn KeyDeriver>>,
+    credentials: Vec<(String, Arc<dyn CredentialsProvider>)>,
+}
+
+impl ClientManagerBuilder {
+    pub fn new() -> Self {
+        Self {
+            config: ManagerConfig::default(),
+            authenticator: Arc::new(StaticAuthenticator),
+            clock: Arc::new(SystemClock),
+            metrics: Arc::new(NoopMetrics),
+            store: None,
+            key_deriver: None,
+            credentials: Vec::new(),
+        }
+    }
+
+    /// Replaces the whole config, including options set before
+    pub fn config(mut self, config: ManagerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// See [`ManagerConfig::refresh_skew`]
+    pub fn refresh_skew(mut self, refresh_skew: i64) -> Self {
+        self.config.refresh_skew = refresh_skew;
+        self
+    }
+
+    /// See [`ManagerConfig::max_capacity`]
+    pub fn max_capacity(mut self, max_capacity: usize) -> Self {
+        self.config.max_capacity = Some(max_capacity);
+        self
+    }
+
+    /// Total time a request of a cached client may take, see [`ClientConfig::timeout`](crate::ClientConfig::timeout)
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.config.client.timeout = Some(timeout);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.config.retry = retry;
+        self
+    }
+
+    pub fn authenticator(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.authenticator = authenticator;
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// See [`ClientManager::with_token_store`], which explains why the in-memory cache stays in
+    /// place
+    pub fn token_store(mut self, store: Arc<dyn TokenStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// See [`ClientManager::with_key_deriver`]
+    pub fn key_deriver(mut self, key_deriver: impl KeyDeriver + 'static) -> Self {
+        self.key_deriver = Some(Arc::new(key_deriver));
+        self
+    }
+
+    /// See [`ClientManager::register_credentials`]
+    pub fn credentials(
+        mut self,
+        name: impl Into<String>,
+        provider: Arc<dyn CredentialsProvider>,
+    ) -> Self {
+        self.credentials.push((name.into(), provider));
+        self
+    }
+
+    /// Creates the manager, which has to happen within a Tokio runtime if
+    /// [`eviction_interval`](ManagerConfig::eviction_interval) is set
+    pub fn build(self) -> ClientManager {
+        let manager = ClientManager::from_parts(
+            self.config,
+            self.authenticator,
+            self.clock,
+            self.metrics,
+            self.store,
+            self.key_deriver,
+        );
+        for (name, provider) in self.credentials {
+            manager.register_credentials(name, provider);
+        }
+
+        manager
+    }
+}
+
+impl Default for ClientManagerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
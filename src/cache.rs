@@ -0,0 +1,731 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use reqwest::header::HeaderMap;
+use reqwest::Client;
+use tokio::time::Instant;
+
+use crate::credentials::{fingerprint, SecretHash};
+use crate::{AccessToken, ApiSecret, AuthConfig, StoredToken};
+
+/// A token along with everything needed to decide whether it can still be used
+///
+/// The token is the unit shared through a [`TokenStore`](crate::TokenStore), unlike the client
+/// built around it, which every process builds for itself, see [`ExpiringClient`].
+pub(crate) struct TokenEntry {
+    pub token: AccessToken,
+    pub issued_at: i64,
+    /// When the token expires on the monotonic [`Clock::instant`](crate::Clock::instant), which
+    /// decides whether it can be used, unlike the wall-clock `expires_at` of the token
+    pub deadline: Instant,
+    /// Can be exchanged for a new token even after this one expired
+    pub refresh_token: Option<RefreshToken>,
+    /// Hash of the secret the token was obtained with
+    pub secret_hash: SecretHash,
+    /// The secret itself, only if credentials are retained for batch refreshes
+    pub api_secret: Option<ApiSecret>,
+    /// How many seconds earlier than the skew alone dictates the token goes stale
+    pub jitter: i64,
+    /// Order in which the authentication that produced the token started, newer ones are higher
+    pub generation: u64,
+    /// Normalized scopes the token was granted, see [`normalize_scopes`]
+    pub scopes: Vec<String>,
+}
+
+impl fmt::Debug for TokenEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenEntry")
+            .field("token", &self.token)
+            .field("issued_at", &self.issued_at)
+            .field("deadline", &self.deadline)
+            .field("has_refresh_token", &self.refresh_token.is_some())
+            .field("jitter", &self.jitter)
+            .field("generation", &self.generation)
+            .field("scopes", &self.scopes)
+            .field("api_secret", &self.api_secret)
+            .finish()
+    }
+}
+
+impl TokenEntry {
+    pub fn new(
+        token: AccessToken,
+        issued_at: i64,
+        deadline: Instant,
+        refresh_token: Option<RefreshToken>,
+        secret_hash: SecretHash,
+    ) -> Self {
+        Self {
+            token,
+            issued_at,
+            deadline,
+            refresh_token,
+            secret_hash,
+            api_secret: None,
+            jitter: 0,
+            generation: 0,
+            scopes: Vec::new(),
+        }
+    }
+
+    pub fn expiration_time(&self) -> i64 {
+        self.token.expires_at()
+    }
+
+    pub fn lifetime(&self) -> i64 {
+        (self.expiration_time() - self.issued_at).max(0)
+    }
+
+    /// Whether the token can still be used at `now`, given the refresh skew and jitter
+    ///
+    /// The skew is clamped to half the lifetime of the token, so that tokens living
+    /// shorter than the skew are still served for a while instead of being refreshed
+    /// on every call.
+    pub fn is_fresh(&self, now: Instant, refresh_skew: i64) -> bool {
+        now < self.stale_at(refresh_skew)
+    }
+
+    /// When the token goes stale, given the refresh skew and jitter
+    pub fn stale_at(&self, refresh_skew: i64) -> Instant {
+        let skew = refresh_skew.clamp(0, self.lifetime() / 2);
+
+        offset(self.deadline, -(skew + self.jitter))
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        now >= self.deadline
+    }
+
+    pub fn with_secret(mut self, api_secret: Option<ApiSecret>) -> Self {
+        self.api_secret = api_secret;
+        self
+    }
+
+    pub fn with_generation(mut self, generation: u64) -> Self {
+        self.generation = generation;
+        self
+    }
+
+    pub fn with_scopes(mut self, scopes: Vec<String>) -> Self {
+        self.scopes = scopes;
+        self
+    }
+
+    /// Whether the token was granted every one of the normalized `scopes`
+    pub fn covers(&self, scopes: &[String]) -> bool {
+        scopes
+            .iter()
+            .all(|scope| self.scopes.binary_search(scope).is_ok())
+    }
+
+    /// Sets a random jitter of up to `window` seconds or `percent` of the lifetime, whichever is
+    /// larger, but at most a quarter of the lifetime
+    pub fn with_jitter(mut self, window: i64, percent: u8, rng: &JitterRng) -> Self {
+        let lifetime = self.lifetime();
+        let window = window
+            .max(lifetime.saturating_mul(i64::from(percent)) / 100)
+            .clamp(0, lifetime / 4);
+        if window > 0 {
+            self.jitter = rng.gen_range(window);
+        }
+
+        self
+    }
+
+    /// The token as persisted by a [`TokenStore`](crate::TokenStore)
+    pub fn stored(&self) -> StoredToken {
+        StoredToken {
+            access_token: self.token.as_str().to_string(),
+            issued_at: self.issued_at,
+            expires_at: self.expiration_time(),
+        }
+    }
+}
+
+/// A refresh token along with the unix timestamp at which it expires, if the backend said so
+#[derive(Clone)]
+pub(crate) struct RefreshToken {
+    pub token: String,
+    pub expires_at: Option<i64>,
+}
+
+impl RefreshToken {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+/// A cached token along with the client authorized with it, which owns this process's
+/// connection pool for the token
+///
+/// Dereferences to the [`TokenEntry`].
+pub(crate) struct ExpiringClient {
+    pub client: Client,
+    pub entry: TokenEntry,
+    /// Value of the cache tick when the client was last inserted or served
+    ///
+    /// Atomic so that serving a client only needs shared access to the cache.
+    last_used: AtomicU64,
+    /// How many times the client was served from the cache
+    hits: AtomicU64,
+    /// The blocking client authorized with the same token, built on first use by
+    /// [`blocking::refresh_client`](crate::blocking::refresh_client)
+    ///
+    /// Shared, so that it's built without holding the lock of the cache.
+    #[cfg(feature = "blocking")]
+    pub blocking: Arc<once_cell::sync::OnceCell<reqwest::blocking::Client>>,
+}
+
+impl fmt::Debug for ExpiringClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExpiringClient")
+            .field("client", &self.client)
+            .field("entry", &self.entry)
+            .field("last_used", &self.last_used)
+            .finish()
+    }
+}
+
+impl ExpiringClient {
+    pub fn new(client: Client, entry: TokenEntry) -> Self {
+        Self {
+            client,
+            entry,
+            last_used: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            #[cfg(feature = "blocking")]
+            blocking: Arc::default(),
+        }
+    }
+}
+
+impl Deref for ExpiringClient {
+    type Target = TokenEntry;
+
+    fn deref(&self) -> &TokenEntry {
+        &self.entry
+    }
+}
+
+/// Draws the jitter of new clients, from a seeded generator if configured
+#[derive(Debug)]
+pub(crate) struct JitterRng(Option<Mutex<StdRng>>);
+
+impl JitterRng {
+    pub fn new(seed: Option<u64>) -> Self {
+        Self(seed.map(|seed| Mutex::new(StdRng::seed_from_u64(seed))))
+    }
+
+    /// A random number of seconds between 0 and `window`, inclusive
+    fn gen_range(&self, window: i64) -> i64 {
+        match &self.0 {
+            Some(rng) => rng.lock().unwrap().gen_range(0..=window),
+            None => rand::thread_rng().gen_range(0..=window),
+        }
+    }
+}
+
+/// Whether a token issued at `issued_at` and expiring at `expires_at` is still fresh at the
+/// unix timestamp `now`, for tokens that weren't cached yet, see [`TokenEntry::is_fresh`]
+pub(crate) fn is_fresh(issued_at: i64, expires_at: i64, now: i64, refresh_skew: i64) -> bool {
+    now < stale_at(issued_at, expires_at, refresh_skew)
+}
+
+/// `secs` seconds after `instant`, or before it if negative, saturating at `instant`
+pub(crate) fn offset(instant: Instant, secs: i64) -> Instant {
+    let duration = Duration::from_secs(secs.unsigned_abs());
+    let offset = if secs < 0 {
+        instant.checked_sub(duration)
+    } else {
+        instant.checked_add(duration)
+    };
+
+    offset.unwrap_or(instant)
+}
+
+/// When a token issued at `issued_at` and expiring at `expires_at` goes stale
+fn stale_at(issued_at: i64, expires_at: i64, refresh_skew: i64) -> i64 {
+    let lifetime = (expires_at - issued_at).max(0);
+    let skew = refresh_skew.clamp(0, lifetime / 2);
+
+    expires_at - skew
+}
+
+/// Identifies a cached client by its api key, the app client and pool it was authenticated
+/// against, the key derived from the context it was requested for and the scopes requested
+///
+/// Tokens minted against different app clients or pools never alias, even for the same api key.
+/// Cheap to clone. The headers of the context the key was derived from are carried along to
+/// rebuild the client, but don't take part in comparisons, see [`KeyDeriver`](crate::KeyDeriver).
+#[derive(Debug, Clone)]
+pub struct CacheKey(Arc<KeyParts>);
+
+#[derive(Debug)]
+struct KeyParts {
+    auth: AuthConfig,
+    api_key: String,
+    derived: String,
+    scopes: Vec<String>,
+    headers: HeaderMap,
+}
+
+impl CacheKey {
+    pub(crate) fn new(
+        auth: AuthConfig,
+        api_key: String,
+        derived: String,
+        headers: HeaderMap,
+    ) -> Self {
+        Self(Arc::new(KeyParts {
+            auth,
+            api_key,
+            derived,
+            scopes: Vec::new(),
+            headers,
+        }))
+    }
+
+    /// The same key, but for a token requested with the normalized `scopes`
+    pub(crate) fn with_scopes(&self, scopes: Vec<String>) -> Self {
+        let KeyParts {
+            auth,
+            api_key,
+            derived,
+            headers,
+            ..
+        } = &*self.0;
+
+        Self(Arc::new(KeyParts {
+            auth: auth.clone(),
+            api_key: api_key.clone(),
+            derived: derived.clone(),
+            scopes,
+            headers: headers.clone(),
+        }))
+    }
+
+    pub fn api_key(&self) -> &str {
+        &self.0.api_key
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.0.auth.client_id
+    }
+
+    pub fn pool_id(&self) -> &str {
+        &self.0.auth.pool_id
+    }
+
+    /// The key derived by the manager's [`KeyDeriver`](crate::KeyDeriver), the api key by default
+    pub fn derived_key(&self) -> &str {
+        &self.0.derived
+    }
+
+    /// The scopes the token was requested with, sorted and without duplicates, empty unless
+    /// requested through [`get_client_with_scope`](crate::ClientManager::get_client_with_scope)
+    pub fn scopes(&self) -> &[String] {
+        &self.0.scopes
+    }
+
+    /// Whether both keys are for the same client, regardless of the scopes
+    pub(crate) fn same_client(&self, other: &CacheKey) -> bool {
+        let (this, other) = (&*self.0, &*other.0);
+
+        this.auth == other.auth && this.api_key == other.api_key && this.derived == other.derived
+    }
+
+    pub(crate) fn auth(&self) -> &AuthConfig {
+        &self.0.auth
+    }
+
+    pub(crate) fn headers(&self) -> &HeaderMap {
+        &self.0.headers
+    }
+}
+
+/// The parts of a [`CacheKey`] that take part in comparisons, so that clients can be looked up
+/// by a [`BorrowedKey`] without allocating a key
+pub(crate) trait KeyView {
+    fn parts(&self) -> (&AuthConfig, &str, &str, &[String]);
+}
+
+impl KeyView for KeyParts {
+    fn parts(&self) -> (&AuthConfig, &str, &str, &[String]) {
+        (&self.auth, &self.api_key, &self.derived, &self.scopes)
+    }
+}
+
+/// A [`CacheKey`] without scopes made of borrowed parts, to be looked up as a `dyn KeyView`
+pub(crate) struct BorrowedKey<'a> {
+    pub auth: &'a AuthConfig,
+    pub api_key: &'a str,
+    pub derived: &'a str,
+}
+
+impl KeyView for BorrowedKey<'_> {
+    fn parts(&self) -> (&AuthConfig, &str, &str, &[String]) {
+        (self.auth, self.api_key, self.derived, &[])
+    }
+}
+
+impl<'a> Borrow<dyn KeyView + 'a> for CacheKey {
+    fn borrow(&self) -> &(dyn KeyView + 'a) {
+        &*self.0
+    }
+}
+
+impl PartialEq for dyn KeyView + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.parts() == other.parts()
+    }
+}
+
+impl Eq for dyn KeyView + '_ {}
+
+impl Hash for dyn KeyView + '_ {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.parts().hash(state);
+    }
+}
+
+// Must agree with the `dyn KeyView` the key borrows as
+impl PartialEq for CacheKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.parts() == other.0.parts()
+    }
+}
+
+impl Eq for CacheKey {}
+
+impl Hash for CacheKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.parts().hash(state);
+    }
+}
+
+/// Sorts the scopes and removes duplicates, so that the same set of scopes always compares equal
+pub(crate) fn normalize_scopes(scopes: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut scopes: Vec<String> = scopes.into_iter().collect();
+    scopes.sort_unstable();
+    scopes.dedup();
+    scopes
+}
+
+/// How a single call obtained its client, see
+/// [`ClientManager::get_client_detailed`](crate::ClientManager::get_client_detailed)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheOutcome {
+    /// A fresh client was cached
+    Hit,
+    /// The call authenticated or waited for a concurrent authentication, because there was no
+    /// fresh client
+    Refreshed,
+    /// The call waited for a concurrent [forced refresh](crate::ClientManager::force_refresh)
+    Forced,
+}
+
+/// A snapshot of a manager's cache, see [`ClientManager::stats`](crate::ClientManager::stats)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    /// The number of cached clients, including expired ones
+    pub total: usize,
+    /// The number of clients that expired but haven't been evicted yet
+    pub expired: usize,
+    /// Unix timestamp at which the next still valid client expires
+    pub next_expiry: Option<i64>,
+    /// How many clients have been evicted so far to stay within `max_capacity`, see
+    /// [`ClientManager::evictions`](crate::ClientManager::evictions)
+    pub evictions: u64,
+}
+
+/// A cached client as reported by [`ClientManager::snapshot`](crate::ClientManager::snapshot),
+/// never including its token or secret
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CacheEntryInfo {
+    /// The first characters of the api key followed by its [fingerprint](crate::ApiKey::fingerprint)
+    pub key_id: String,
+    pub client_id: String,
+    pub pool_id: String,
+    /// Unix timestamp at which the token expires
+    pub expires_at: i64,
+    /// Seconds until the token expires, 0 if it already has
+    pub remaining: i64,
+    /// Unix timestamp at which the token was obtained, see [`ClientManager::issued_at`](crate::ClientManager::issued_at)
+    pub refreshed_at: i64,
+    /// How many times the client was served from the cache
+    pub hits: u64,
+    /// The scopes the token was granted, sorted
+    pub scopes: Vec<String>,
+}
+
+/// Short keys are identified by their fingerprint only, so that the prefix never reveals most of the key
+fn key_id(api_key: &str) -> String {
+    if api_key.chars().count() < 8 {
+        return fingerprint(api_key);
+    }
+    let prefix: String = api_key.chars().take(4).collect();
+
+    format!("{prefix}..{}", fingerprint(api_key))
+}
+
+/// The clients cached by a manager, optionally bounded in size
+///
+/// Once `capacity` is exceeded expired clients are dropped first, then the least recently used ones.
+/// Finding the least recently used client is a linear scan, which is fine for the few
+/// thousand clients a manager is expected to hold.
+///
+/// Only inserting and removing clients needs exclusive access, serving them doesn't.
+#[derive(Debug)]
+pub(crate) struct Cache {
+    entries: HashMap<CacheKey, ExpiringClient>,
+    capacity: Option<usize>,
+    tick: AtomicU64,
+}
+
+impl Cache {
+    pub fn new(capacity: Option<usize>) -> Self {
+        Self {
+            entries: HashMap::new(),
+            capacity,
+            tick: AtomicU64::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&CacheKey, &ExpiringClient)> {
+        self.entries.iter()
+    }
+
+    pub fn stats(&self, now: Instant) -> CacheStats {
+        let mut stats = CacheStats {
+            total: self.entries.len(),
+            ..CacheStats::default()
+        };
+        for client in self.entries.values() {
+            if client.is_expired(now) {
+                stats.expired += 1;
+            } else {
+                let expiry = client.expiration_time();
+                stats.next_expiry = Some(stats.next_expiry.map_or(expiry, |next| next.min(expiry)));
+            }
+        }
+
+        stats
+    }
+
+    pub fn snapshot(&self, now: Instant) -> Vec<CacheEntryInfo> {
+        self.entries
+            .iter()
+            .map(|(key, client)| CacheEntryInfo {
+                key_id: key_id(key.api_key()),
+                client_id: key.client_id().to_string(),
+                pool_id: key.pool_id().to_string(),
+                expires_at: client.expiration_time(),
+                remaining: client.deadline.saturating_duration_since(now).as_secs() as i64,
+                refreshed_at: client.issued_at,
+                hits: client.hits.load(Ordering::Relaxed),
+                scopes: client.scopes.clone(),
+            })
+            .collect()
+    }
+
+    /// Returns the client for `key` without marking it as used
+    pub fn peek(&self, key: &CacheKey) -> Option<&ExpiringClient> {
+        self.entries.get(key)
+    }
+
+    /// Returns the client for `key` unless it already expired at `now`, marking it as the
+    /// most recently used one
+    ///
+    /// Expired clients are left in place, their refresh token may still be used for the next
+    /// authentication or they may be served as a fallback. They're replaced or evicted later on.
+    pub fn get<Q>(&self, key: &Q, now: Instant) -> Option<&ExpiringClient>
+    where
+        CacheKey: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let client = self.entries.get(key)?;
+        if client.is_expired(now) {
+            return None;
+        }
+
+        client.last_used.store(self.next_tick(), Ordering::Relaxed);
+        client.hits.fetch_add(1, Ordering::Relaxed);
+
+        Some(client)
+    }
+
+    /// Inserts `client`, returning the keys evicted to stay within the capacity
+    pub fn insert(&mut self, key: CacheKey, client: ExpiringClient, now: Instant) -> Vec<CacheKey> {
+        client.last_used.store(self.next_tick(), Ordering::Relaxed);
+        self.entries.insert(key, client);
+
+        let mut evicted = Vec::new();
+        let Some(capacity) = self.capacity else {
+            return evicted;
+        };
+
+        if self.entries.len() > capacity {
+            self.entries.retain(|key, client| {
+                let expired = client.is_expired(now);
+                if expired {
+                    evicted.push(key.clone());
+                }
+
+                !expired
+            });
+        }
+
+        while self.entries.len() > capacity {
+            let Some(lru) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, client)| client.last_used.load(Ordering::Relaxed))
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+
+            self.entries.remove(&lru);
+            evicted.push(lru);
+        }
+
+        evicted
+    }
+
+    fn next_tick(&self) -> u64 {
+        self.tick.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn remove(&mut self, key: &CacheKey) -> Option<ExpiringClient> {
+        self.entries.remove(key)
+    }
+
+    /// Removes all clients, returning their keys
+    pub fn clear(&mut self) -> Vec<CacheKey> {
+        self.entries.drain().map(|(key, _)| key).collect()
+    }
+
+    /// Keeps only the clients for which `f` returns `true`, returning the keys of the removed ones
+    pub fn retain(
+        &mut self,
+        mut f: impl FnMut(&CacheKey, &ExpiringClient) -> bool,
+    ) -> Vec<CacheKey> {
+        let mut removed = Vec::new();
+        self.entries.retain(|key, client| {
+            let keep = f(key, client);
+            if !keep {
+                removed.push(key.clone());
+            }
+
+            keep
+        });
+
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credentials::SecretHasher;
+
+    fn key(api_key: &str) -> CacheKey {
+        CacheKey::new(
+            AuthConfig::default(),
+            api_key.to_string(),
+            api_key.to_string(),
+            HeaderMap::new(),
+        )
+    }
+
+    /// A token issued at 1000 for an hour, expiring an hour after `now`
+    fn entry(now: Instant) -> TokenEntry {
+        TokenEntry::new(
+            AccessToken::new("token", 4_600),
+            1_000,
+            now + Duration::from_secs(3_600),
+            None,
+            SecretHasher::new().hash(&ApiSecret::new("secret")),
+        )
+    }
+
+    fn client(now: Instant) -> ExpiringClient {
+        ExpiringClient::new(Client::new(), entry(now))
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_client() {
+        let now = Instant::now();
+        let mut cache = Cache::new(Some(2));
+        assert!(cache.insert(key("a"), client(now), now).is_empty());
+        assert!(cache.insert(key("b"), client(now), now).is_empty());
+
+        // Inserted first, but used last
+        assert!(cache.get(&key("a"), now).is_some());
+        let evicted = cache.insert(key("c"), client(now), now);
+
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0], key("b"));
+        assert!(cache.peek(&key("a")).is_some());
+        assert!(cache.peek(&key("c")).is_some());
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn evicts_expired_clients_before_used_ones() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(3_600);
+        let mut cache = Cache::new(Some(2));
+        cache.insert(key("a"), client(now), now);
+        cache.insert(key("b"), client(later), now);
+        // Used last, but expired by the time `c` is inserted
+        cache.get(&key("a"), now);
+
+        let evicted = cache.insert(key("c"), client(later), later);
+
+        assert_eq!(evicted, [key("a")]);
+        assert!(cache.peek(&key("b")).is_some());
+    }
+
+    #[test]
+    fn scopes_are_covered_regardless_of_order() {
+        let scopes = |scopes: &[&str]| normalize_scopes(scopes.iter().map(|s| s.to_string()));
+        let entry = entry(Instant::now()).with_scopes(scopes(&["write", "read", "read"]));
+
+        assert_eq!(entry.scopes, ["read", "write"]);
+        assert!(entry.covers(&scopes(&["write", "read"])));
+        assert!(entry.covers(&scopes(&["read"])));
+        assert!(!entry.covers(&scopes(&["admin"])));
+        assert!(!entry.covers(&scopes(&["read", "write", "admin"])));
+    }
+
+    #[test]
+    fn jitter_is_spread_within_a_quarter_of_the_lifetime() {
+        let rng = JitterRng::new(None);
+        let jitters: Vec<_> = (0..200)
+            .map(|i| {
+                // Larger windows than a quarter of the lifetime, given in seconds or percent
+                let (window, percent) = if i % 2 == 0 { (3_600, 0) } else { (0, 50) };
+                entry(Instant::now())
+                    .with_jitter(window, percent, &rng)
+                    .jitter
+            })
+            .collect();
+
+        assert!(jitters.iter().all(|jitter| (0..=900).contains(jitter)));
+        assert!(jitters.iter().any(|jitter| *jitter != jitters[0]));
+    }
+}
@@ -0,0 +1,125 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Source of the current time used for all expiration decisions
+pub trait Clock: Send + Sync {
+    /// Current unix timestamp in seconds
+    fn now(&self) -> i64;
+
+    /// Current monotonic time, which decides when cached tokens expire
+    ///
+    /// Every token gets a deadline on this clock when it's cached, its `expires_at` is derived
+    /// from [`now`](Self::now) and only reported. Steps of the wall clock, e.g. by NTP, don't
+    /// affect the deadline, so expired tokens don't become valid again when the wall clock steps
+    /// backwards and valid ones don't expire early when it steps forwards.
+    ///
+    /// Defaults to [`tokio::time::Instant::now`], which follows the time of a paused Tokio
+    /// runtime.
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Any `Fn() -> i64` returning a unix timestamp in seconds can serve as a clock
+impl<F> Clock for F
+where
+    F: Fn() -> i64 + Send + Sync,
+{
+    fn now(&self) -> i64 {
+        self()
+    }
+}
+
+/// The wall clock, backed by `chrono::Utc::now()`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        chrono::Utc::now().timestamp()
+    }
+}
+
+/// A clock that only moves when told to
+///
+/// Clones share the same time, so a test can keep a handle and advance
+/// the clock of a manager it has been handed to. [`advance`](Self::advance) lets time pass on
+/// both the wall and the monotonic clock, while [`set`](Self::set) steps the wall clock only,
+/// like NTP would:
+///
+/// ```
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), existing_code_challenge::Error> {
+/// use std::sync::Arc;
+///
+/// use existing_code_challenge::{CacheOutcome, ClientManager, MockClock};
+///
+/// // The default authenticator's tokens last an hour
+/// let clock = MockClock::new(1_000);
+/// let manager = ClientManager::builder()
+///     .clock(Arc::new(clock.clone()))
+///     .build();
+/// manager.get_client("key", "secret").await?;
+///
+/// // Stepping the wall clock past the expiration doesn't invalidate the token
+/// clock.set(10_000);
+/// let (_, outcome) = manager.get_client_detailed("key", "secret").await?;
+/// assert_eq!(outcome, CacheOutcome::Hit);
+///
+/// // Nor does stepping it back keep the token once it goes stale
+/// clock.set(0);
+/// clock.advance(3_600);
+/// let (_, outcome) = manager.get_client_detailed("key", "secret").await?;
+/// assert_eq!(outcome, CacheOutcome::Refreshed);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<AtomicI64>,
+    /// Seconds passed on the monotonic clock since `origin`
+    elapsed: Arc<AtomicI64>,
+    origin: Instant,
+}
+
+impl MockClock {
+    pub fn new(now: i64) -> Self {
+        Self {
+            now: Arc::new(AtomicI64::new(now)),
+            elapsed: Arc::new(AtomicI64::new(0)),
+            origin: Instant::now(),
+        }
+    }
+
+    /// Steps the wall clock to `now`, without any time passing on the monotonic clock
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    /// Lets `secs` seconds pass, only stepping the wall clock back if negative
+    pub fn advance(&self, secs: i64) {
+        self.now.fetch_add(secs, Ordering::SeqCst);
+        self.elapsed.fetch_add(secs.max(0), Ordering::SeqCst);
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
+
+    fn instant(&self) -> Instant {
+        let elapsed = self.elapsed.load(Ordering::SeqCst).max(0) as u64;
+
+        self.origin + Duration::from_secs(elapsed)
+    }
+}
@@ -0,0 +1,893 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderName, AUTHORIZATION};
+use reqwest::{ClientBuilder, Proxy, StatusCode};
+
+use crate::headers::{sends_api_key, X_API_KEY};
+use crate::{AuthScheme, Error, RetryPolicy};
+
+// Public auth input data
+const CLIENT_ID: &str = "1bpd19lcr33qvg5cr3oi79rdap";
+const POOL_ID: &str = "us-west-2_iLmIggsiy";
+
+const DEFAULT_REFRESH_SKEW: i64 = 60;
+const DEFAULT_NEGATIVE_TTL: i64 = 10;
+const DEFAULT_MAX_LIFETIME: i64 = 30 * 24 * 60 * 60;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Identifies the Cognito user pool and app client to authenticate against
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct AuthConfig {
+    pub client_id: String,
+    pub pool_id: String,
+}
+
+impl AuthConfig {
+    pub fn new(client_id: impl Into<String>, pool_id: impl Into<String>) -> Self {
+        Self {
+            client_id: client_id.into(),
+            pool_id: pool_id.into(),
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self::new(CLIENT_ID, POOL_ID)
+    }
+}
+
+/// The subset of [`ClientBuilder`] options applied to every cached client
+///
+/// Each field maps to the builder method of the same name, `None` leaves the reqwest default in place.
+/// The options are fixed per manager and apply to all of its clients alike, so they don't
+/// take part in the cache key.
+///
+/// # Connection pools
+///
+/// Every cached client owns a connection pool, as reqwest ties the default headers carrying the
+/// token to the client and can't share a pool between clients. Many api keys talking to the same
+/// host therefore hold many pools, each costing some memory and a file descriptor per open connection.
+/// Idle connections are bounded by `max_capacity` times `pool_max_idle_per_host` per host, see
+/// [`ClientManager::max_idle_connections_per_host`](crate::ClientManager::max_idle_connections_per_host),
+/// and closed after `pool_idle_timeout`. Lowering those trades file descriptors for more connection
+/// setups under load, connections in use aren't limited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct ClientConfig {
+    /// Total time a request may take, from connecting until the response body is read
+    #[cfg_attr(feature = "serde", serde(with = "secs::option"))]
+    pub timeout: Option<Duration>,
+    #[cfg_attr(feature = "serde", serde(with = "secs::option"))]
+    pub connect_timeout: Option<Duration>,
+    /// How long idle connections are kept in the pool
+    #[cfg_attr(feature = "serde", serde(with = "secs::option"))]
+    pub pool_idle_timeout: Option<Duration>,
+    /// Idle connections kept per host by the pool of each client, unbounded by default
+    pub pool_max_idle_per_host: usize,
+    pub user_agent: Option<String>,
+    /// URL of a proxy all requests are sent through
+    pub proxy: Option<String>,
+    /// Customizes the builder beyond the options above, right before the authentication
+    /// headers are added, which it therefore can't remove or override
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub builder_hook: Option<BuilderHook>,
+}
+
+impl ClientConfig {
+    pub(crate) fn apply(&self, mut builder: ClientBuilder) -> reqwest::Result<ClientBuilder> {
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+
+        builder = builder
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .pool_max_idle_per_host(self.pool_max_idle_per_host);
+        if let Some(hook) = &self.builder_hook {
+            builder = (hook.0)(builder);
+        }
+
+        Ok(builder)
+    }
+
+    /// Like [`apply`](Self::apply), but for blocking clients, which the `builder_hook` doesn't apply to
+    #[cfg(feature = "blocking")]
+    pub(crate) fn apply_blocking(
+        &self,
+        mut builder: reqwest::blocking::ClientBuilder,
+    ) -> reqwest::Result<reqwest::blocking::ClientBuilder> {
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+
+        Ok(builder
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .pool_max_idle_per_host(self.pool_max_idle_per_host))
+    }
+}
+
+/// A function customizing the [`ClientBuilder`] of every cached client, see [`ClientConfig::builder_hook`]
+///
+/// Hooks are only equal to their clones.
+#[derive(Clone)]
+pub struct BuilderHook(Arc<dyn Fn(ClientBuilder) -> ClientBuilder + Send + Sync>);
+
+impl BuilderHook {
+    pub fn new(hook: impl Fn(ClientBuilder) -> ClientBuilder + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+}
+
+impl fmt::Debug for BuilderHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BuilderHook(..)")
+    }
+}
+
+impl PartialEq for BuilderHook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for BuilderHook {}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Some(DEFAULT_TIMEOUT),
+            connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+            pool_idle_timeout: Some(DEFAULT_POOL_IDLE_TIMEOUT),
+            pool_max_idle_per_host: usize::MAX,
+            user_agent: None,
+            proxy: None,
+            builder_hook: None,
+        }
+    }
+}
+
+/// Controls the background refreshes of clients opted in with
+/// [`ClientManager::keep_warm`](crate::ClientManager::keep_warm)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct KeepWarmConfig {
+    /// How often clients are checked
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub interval: Duration,
+    /// How many seconds before going stale a client is refreshed
+    pub lead: i64,
+    /// Clients that haven't been requested for this many seconds are no longer kept warm
+    pub idle_timeout: i64,
+    /// Upper bound in seconds of the exponential backoff after failed refreshes
+    pub max_backoff: i64,
+}
+
+impl Default for KeepWarmConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            lead: 30,
+            idle_timeout: 15 * 60,
+            max_backoff: 5 * 60,
+        }
+    }
+}
+
+/// Fails authentications right away for a while after the backend failed repeatedly,
+/// instead of making every caller wait for it to fail again
+///
+/// Only failures the [`Authenticator`](crate::Authenticator) classifies as
+/// [retryable](crate::Authenticator::is_retryable) count, a rejection shows that the backend is up.
+/// See [`ClientManager::circuit_state`](crate::ClientManager::circuit_state).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct CircuitBreakerConfig {
+    /// How many consecutive failures open the circuit
+    pub failure_threshold: u32,
+    /// Seconds within which the failures have to occur, counted from the first one
+    pub window: i64,
+    /// Seconds the circuit stays open before a single probe is let through
+    pub cooldown: i64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            window: 60,
+            cooldown: 30,
+        }
+    }
+}
+
+/// Limits how often each key is authenticated, as a token bucket refilled at `per_second`
+///
+/// Only authentications count, cached clients are always served. An authentication beyond the
+/// limit waits for up to `max_wait` and fails with [`Error::RateLimited`](crate::Error::RateLimited)
+/// if it would have to wait longer. See [`ClientManager::rate_limit_state`](crate::ClientManager::rate_limit_state).
+///
+/// ```
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// use existing_code_challenge::{ClientManager, Error, MockClock, RateLimitConfig};
+///
+/// let clock = MockClock::new(1_000);
+/// let manager = ClientManager::builder()
+///     .clock(Arc::new(clock.clone()))
+///     .config(existing_code_challenge::ManagerConfig {
+///         rate_limit: Some(RateLimitConfig {
+///             burst: 2,
+///             per_second: 0.5,
+///             max_wait: Duration::ZERO,
+///         }),
+///         ..Default::default()
+///     })
+///     .build();
+///
+/// assert!(manager.force_refresh("key", "secret").await.is_ok());
+/// assert!(manager.force_refresh("key", "secret").await.is_ok());
+/// assert!(matches!(
+///     manager.force_refresh("key", "secret").await,
+///     Err(Error::RateLimited { retry_after }) if retry_after == Duration::from_secs(2),
+/// ));
+///
+/// clock.advance(2);
+/// assert!(manager.force_refresh("key", "secret").await.is_ok());
+/// // Cached clients are served regardless
+/// assert!(manager.get_client("key", "secret").await.is_ok());
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct RateLimitConfig {
+    /// How many authentications of a key may happen back to back
+    pub burst: u32,
+    /// How many authentications of a key per second are sustained
+    pub per_second: f64,
+    /// How long an authentication beyond the limit waits, failing right away if zero
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub max_wait: Duration,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            burst: 5,
+            per_second: 1.0,
+            max_wait: Duration::ZERO,
+        }
+    }
+}
+
+/// When a request answered as unauthorized is sent again with a refreshed token, by
+/// [`ClientManager::request_with_retry`](crate::ClientManager::request_with_retry) and an
+/// [`AuthedClient`](crate::AuthedClient)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayPolicy {
+    /// How many times a request is sent again, each time after a forced refresh, disabled if 0
+    pub max_replays: u32,
+    /// Response statuses showing that the token was rejected
+    pub statuses: Vec<StatusCode>,
+}
+
+impl Default for ReplayPolicy {
+    fn default() -> Self {
+        Self {
+            max_replays: 1,
+            statuses: vec![StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN],
+        }
+    }
+}
+
+/// The level of an event logged with the `tracing` feature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum LogLevel {
+    Off,
+    Error,
+    #[default]
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Whether a manager builds a client for every token or sends every request through one client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ClientMode {
+    /// Every token gets its own client, with the authentication headers as its default headers
+    ///
+    /// A refresh builds a new client and so a new connection pool.
+    #[default]
+    PerToken,
+    /// One long-lived client is shared by all tokens, and the authentication headers are added to
+    /// every request instead
+    ///
+    /// Refreshes never tear down the connection pool. The client returned by
+    /// [`get_client`](crate::ClientManager::get_client) and alike is the shared one and sends
+    /// requests without credentials, requests have to be made through
+    /// [`authorized_request`](crate::ClientManager::authorized_request), an
+    /// [`AuthedClient`](crate::AuthedClient) or [`request_with_retry`](crate::ClientManager::request_with_retry).
+    Shared,
+}
+
+/// Configuration of a [`ClientManager`](crate::ClientManager)
+///
+/// With the `serde` feature it can be deserialized, e.g. from a section of a config file. Durations
+/// are given in seconds, fields that are left out keep their default, and the `replay`,
+/// `extra_headers`, `auth_scheme` and `api_key_header` are always left at their defaults, as they
+/// can't be written down.
+///
+/// ```
+/// # #[cfg(feature = "serde")]
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::time::Duration;
+///
+/// use existing_code_challenge::{ClientManager, ManagerConfig};
+///
+/// let config: ManagerConfig = toml::from_str(
+///     r#"
+///     refresh_skew = 120
+///     expiry_jitter_percent = 10
+///     auth_timeout = 2.5
+///     max_capacity = 1000
+///     eviction_interval = 60
+///
+///     [auth]
+///     client_id = "client"
+///     pool_id = "pool"
+///
+///     [retry]
+///     max_attempts = 5
+///     base_delay = 0.1
+///     "#,
+/// )?;
+/// assert_eq!(config.auth.client_id, "client");
+/// assert_eq!(config.auth_timeout, Some(Duration::from_millis(2_500)));
+/// assert_eq!(config.retry.max_attempts, 5);
+/// assert_eq!(config.max_lifetime, ManagerConfig::default().max_lifetime);
+///
+/// let manager = ClientManager::from_config(config)?;
+/// # drop(manager);
+///
+/// // Typos aren't ignored
+/// assert!(toml::from_str::<ManagerConfig>("refresh_skw = 120").is_err());
+/// # Ok(())
+/// # }
+/// # #[cfg(not(feature = "serde"))]
+/// # fn main() {}
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct ManagerConfig {
+    pub auth: AuthConfig,
+    /// How many seconds before the token expires the client is considered stale and re-authenticated
+    ///
+    /// Clamped to half the lifetime of the token.
+    pub refresh_skew: i64,
+    /// Upper bound in seconds of a random amount each client goes stale earlier than `refresh_skew` dictates
+    ///
+    /// Spreads out the refreshes of many processes that authenticated at the same time.
+    /// Clamped to a quarter of the lifetime of the token, disabled if 0.
+    pub expiry_jitter: i64,
+    /// Like `expiry_jitter`, but as a percentage of the lifetime of each token, the larger of both applies
+    ///
+    /// Spreads out the refreshes of many keys authenticated at once, e.g. right after a deploy.
+    /// Subject to the same clamp, disabled if 0.
+    pub expiry_jitter_percent: u8,
+    /// Seed of the random jitter, so that tests see the same jitter on every run
+    ///
+    /// Drawn from the thread-local generator if `None`.
+    pub jitter_seed: Option<u64>,
+    pub retry: RetryPolicy,
+    /// How long a single call to the [`Authenticator`](crate::Authenticator) may take before
+    /// failing with [`Error::AuthTimeout`](crate::Error::AuthTimeout), unbounded if `None`
+    ///
+    /// Timed out calls count as transient failures, so they're retried according to the
+    /// [`retry`](Self::retry) policy like any other transient failure, each attempt getting the
+    /// full timeout. Callers may thus wait for up to `max_attempts` timeouts plus the backoff in
+    /// between, use [`RetryPolicy::none`](crate::RetryPolicy::none) to fail after the first one.
+    #[cfg_attr(feature = "serde", serde(with = "secs::option"))]
+    pub auth_timeout: Option<Duration>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub replay: ReplayPolicy,
+    /// Disabled if `None`
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// The level failed authentications are logged at with the `tracing` feature
+    ///
+    /// Events identify the key by its [fingerprint](crate::ApiKey::fingerprint), and carry the
+    /// error with every occurrence of the secret redacted. Tokens are never logged.
+    pub failure_log_level: LogLevel,
+    /// Disabled if `None`
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Tokens that expire in fewer seconds are refused with [`Error::InvalidLifetime`](crate::Error::InvalidLifetime)
+    ///
+    /// Caching such a token would re-authenticate on nearly every call.
+    pub min_lifetime: i64,
+    /// Tokens claiming a longer lifetime in seconds are treated as expiring after this long instead,
+    /// reported to [`Metrics::on_lifetime_clamped`](crate::Metrics::on_lifetime_clamped)
+    ///
+    /// Bounds how long a revoked token keeps being used, 30 days by default. The `refresh_skew` and
+    /// jitter count from the clamped expiration, and the skew is clamped to half of the clamped
+    /// lifetime, so a token is refreshed at least that often.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), existing_code_challenge::Error> {
+    /// use std::sync::Arc;
+    ///
+    /// use existing_code_challenge::{ClientManager, ManagerConfig, MockClock};
+    ///
+    /// // The default authenticator claims tokens last an hour
+    /// let manager = ClientManager::builder()
+    ///     .clock(Arc::new(MockClock::new(1_000)))
+    ///     .config(ManagerConfig {
+    ///         max_lifetime: 600,
+    ///         ..ManagerConfig::default()
+    ///     })
+    ///     .build();
+    ///
+    /// let (_, expires_at) = manager.get_client_with_expiry("key", "secret").await?;
+    /// assert_eq!(expires_at, 1_600);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub max_lifetime: i64,
+    /// For how many seconds rejected credentials fail right away without asking the backend again
+    ///
+    /// Only failures that aren't [retryable](crate::Authenticator::is_retryable) are remembered,
+    /// disabled if 0.
+    pub negative_ttl: i64,
+    /// Serve the cached client if a refresh fails with a [retryable](crate::Authenticator::is_retryable)
+    /// error, for up to this many seconds past its expiration, instead of returning the error
+    ///
+    /// Trades strict correctness for availability during a brief outage of the backend,
+    /// disabled if `None`. Forced refreshes and rejected credentials always fail.
+    pub serve_stale_for: Option<i64>,
+    /// Additional default headers of every client, on top of the authentication headers
+    ///
+    /// Must not contain any of the headers set by the `auth_scheme`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub extra_headers: HeaderMap,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub auth_scheme: AuthScheme,
+    /// The header the api key is sent in along with the token, `X-Api-Key` by default and
+    /// omitted if `None`
+    ///
+    /// Only applies to [`AuthScheme`]s that send the api key, and must not be a header the scheme
+    /// sets itself.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), existing_code_challenge::Error> {
+    /// use existing_code_challenge::{ClientManager, ClientMode, ManagerConfig};
+    /// use reqwest::header::HeaderName;
+    /// use reqwest::Method;
+    ///
+    /// let headers = |api_key_header| async move {
+    ///     let manager = ClientManager::new(ManagerConfig {
+    ///         api_key_header,
+    ///         // Puts the headers on every request instead of the client, where they can't be inspected
+    ///         client_mode: ClientMode::Shared,
+    ///         ..ManagerConfig::default()
+    ///     });
+    ///     let request = manager
+    ///         .authorized_request(Method::GET, "https://example.com", "key", "secret")
+    ///         .await?
+    ///         .build()
+    ///         .unwrap();
+    ///
+    ///     Ok::<_, existing_code_challenge::Error>(request.headers().clone())
+    /// };
+    ///
+    /// let included = headers(ManagerConfig::default().api_key_header).await?;
+    /// assert_eq!(included["x-api-key"], "key");
+    ///
+    /// let omitted = headers(None).await?;
+    /// assert!(omitted.contains_key("authorization"));
+    /// assert!(!omitted.contains_key("x-api-key"));
+    ///
+    /// let renamed = headers(Some(HeaderName::from_static("x-gateway-key"))).await?;
+    /// assert_eq!(renamed["x-gateway-key"], "key");
+    /// assert!(!renamed.contains_key("x-api-key"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub api_key_header: Option<HeaderName>,
+    pub client: ClientConfig,
+    pub client_mode: ClientMode,
+    /// How often a background task evicts expired clients, disabled if `None`
+    ///
+    /// The task is spawned when the manager is created, which then has to happen
+    /// within a Tokio runtime, and stops once the last clone of the manager is dropped or on
+    /// [`shutdown`](crate::ClientManager::shutdown).
+    #[cfg_attr(feature = "serde", serde(with = "secs::option"))]
+    pub eviction_interval: Option<Duration>,
+    pub keep_warm: KeepWarmConfig,
+    /// Whether every cached client holds on to its secret, so that
+    /// [`refresh_all_expired`](crate::ClientManager::refresh_all_expired) can refresh it
+    ///
+    /// Off by default, as it keeps secrets in memory for as long as their clients are cached.
+    pub retain_credentials: bool,
+    /// How many clients [`warm_up`](crate::ClientManager::warm_up) and
+    /// [`refresh_all_expired`](crate::ClientManager::refresh_all_expired) authenticate concurrently
+    pub refresh_concurrency: usize,
+    /// Maximum number of cached clients, least recently used ones are evicted beyond it
+    pub max_capacity: Option<usize>,
+    /// How many seconds past their expiration clients are kept around before being evicted
+    pub eviction_grace: i64,
+}
+
+impl Default for ManagerConfig {
+    fn default() -> Self {
+        Self {
+            auth: AuthConfig::default(),
+            refresh_skew: DEFAULT_REFRESH_SKEW,
+            expiry_jitter: 0,
+            expiry_jitter_percent: 0,
+            jitter_seed: None,
+            retry: RetryPolicy::default(),
+            auth_timeout: Some(DEFAULT_AUTH_TIMEOUT),
+            replay: ReplayPolicy::default(),
+            circuit_breaker: None,
+            failure_log_level: LogLevel::default(),
+            rate_limit: None,
+            min_lifetime: 1,
+            max_lifetime: DEFAULT_MAX_LIFETIME,
+            negative_ttl: DEFAULT_NEGATIVE_TTL,
+            serve_stale_for: None,
+            extra_headers: HeaderMap::new(),
+            auth_scheme: AuthScheme::default(),
+            api_key_header: Some(X_API_KEY),
+            client: ClientConfig::default(),
+            client_mode: ClientMode::default(),
+            eviction_interval: None,
+            keep_warm: KeepWarmConfig::default(),
+            retain_credentials: false,
+            refresh_concurrency: 4,
+            max_capacity: None,
+            eviction_grace: 0,
+        }
+    }
+}
+
+impl ManagerConfig {
+    /// Checks that every field has a value the manager can work with, naming the first one that
+    /// doesn't in [`Error::InvalidConfig`]
+    ///
+    /// [`ClientManager::from_config`](crate::ClientManager::from_config) validates the config,
+    /// the other constructors take it as is.
+    ///
+    /// ```
+    /// use existing_code_challenge::{Error, ManagerConfig};
+    ///
+    /// assert!(ManagerConfig::default().validate().is_ok());
+    ///
+    /// let config = ManagerConfig {
+    ///     refresh_skew: 600,
+    ///     max_lifetime: 300,
+    ///     ..ManagerConfig::default()
+    /// };
+    /// assert!(matches!(
+    ///     config.validate(),
+    ///     Err(Error::InvalidConfig { field: "refresh_skew", .. }),
+    /// ));
+    /// ```
+    pub fn validate(&self) -> Result<(), Error> {
+        fn check(valid: bool, field: &'static str, reason: &'static str) -> Result<(), Error> {
+            match valid {
+                true => Ok(()),
+                false => Err(Error::InvalidConfig { field, reason }),
+            }
+        }
+
+        check(
+            !self.auth.client_id.is_empty(),
+            "auth.client_id",
+            "must not be empty",
+        )?;
+        check(
+            !self.auth.pool_id.is_empty(),
+            "auth.pool_id",
+            "must not be empty",
+        )?;
+        check(
+            self.refresh_skew >= 0,
+            "refresh_skew",
+            "must not be negative",
+        )?;
+        check(
+            self.expiry_jitter >= 0,
+            "expiry_jitter",
+            "must not be negative",
+        )?;
+        check(
+            self.expiry_jitter_percent <= 100,
+            "expiry_jitter_percent",
+            "must be at most 100",
+        )?;
+        check(
+            self.min_lifetime >= 0,
+            "min_lifetime",
+            "must not be negative",
+        )?;
+        check(self.max_lifetime > 0, "max_lifetime", "must be positive")?;
+        check(
+            self.min_lifetime <= self.max_lifetime,
+            "min_lifetime",
+            "must not exceed `max_lifetime`",
+        )?;
+        check(
+            self.refresh_skew < self.max_lifetime,
+            "refresh_skew",
+            "must be below `max_lifetime`",
+        )?;
+        check(
+            self.expiry_jitter < self.max_lifetime,
+            "expiry_jitter",
+            "must be below `max_lifetime`",
+        )?;
+        check(
+            self.negative_ttl >= 0,
+            "negative_ttl",
+            "must not be negative",
+        )?;
+        check(
+            !matches!(self.serve_stale_for, Some(secs) if secs < 0),
+            "serve_stale_for",
+            "must not be negative",
+        )?;
+        check(
+            self.eviction_grace >= 0,
+            "eviction_grace",
+            "must not be negative",
+        )?;
+        check(
+            self.max_capacity != Some(0),
+            "max_capacity",
+            "must be positive",
+        )?;
+
+        let retry = &self.retry;
+        check(
+            retry.max_attempts > 0,
+            "retry.max_attempts",
+            "must be positive",
+        )?;
+        check(
+            retry.multiplier.is_finite() && retry.multiplier >= 1.0,
+            "retry.multiplier",
+            "must be at least 1",
+        )?;
+        check(
+            retry.base_delay <= retry.max_delay,
+            "retry.base_delay",
+            "must not exceed `retry.max_delay`",
+        )?;
+        check(
+            self.auth_timeout != Some(Duration::ZERO),
+            "auth_timeout",
+            "must not be zero",
+        )?;
+        check(
+            self.eviction_interval != Some(Duration::ZERO),
+            "eviction_interval",
+            "must not be zero",
+        )?;
+
+        let keep_warm = &self.keep_warm;
+        check(
+            !keep_warm.interval.is_zero(),
+            "keep_warm.interval",
+            "must not be zero",
+        )?;
+        check(
+            keep_warm.lead >= 0,
+            "keep_warm.lead",
+            "must not be negative",
+        )?;
+        check(
+            keep_warm.idle_timeout >= 0,
+            "keep_warm.idle_timeout",
+            "must not be negative",
+        )?;
+        check(
+            keep_warm.max_backoff >= 0,
+            "keep_warm.max_backoff",
+            "must not be negative",
+        )?;
+
+        if let Some(breaker) = &self.circuit_breaker {
+            check(
+                breaker.failure_threshold > 0,
+                "circuit_breaker.failure_threshold",
+                "must be positive",
+            )?;
+            check(
+                breaker.window >= 0,
+                "circuit_breaker.window",
+                "must not be negative",
+            )?;
+            check(
+                breaker.cooldown >= 0,
+                "circuit_breaker.cooldown",
+                "must not be negative",
+            )?;
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            check(rate_limit.burst > 0, "rate_limit.burst", "must be positive")?;
+            check(
+                rate_limit.per_second.is_finite() && rate_limit.per_second > 0.0,
+                "rate_limit.per_second",
+                "must be positive",
+            )?;
+        }
+        check(
+            !(sends_api_key(&self.auth_scheme) && self.api_key_header == Some(AUTHORIZATION)),
+            "api_key_header",
+            "must not be the `Authorization` header set by the `auth_scheme`",
+        )?;
+        if let Some(proxy) = &self.client.proxy {
+            check(
+                Proxy::all(proxy).is_ok(),
+                "client.proxy",
+                "must be a valid proxy URL",
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<AuthConfig> for ManagerConfig {
+    fn from(auth: AuthConfig) -> Self {
+        Self {
+            auth,
+            ..Default::default()
+        }
+    }
+}
+
+/// Durations given in seconds, as an integer or a fraction
+#[cfg(feature = "serde")]
+pub(crate) mod secs {
+    use std::time::Duration;
+
+    use serde::{de, Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let secs = f64::deserialize(deserializer)?;
+        Duration::try_from_secs_f64(secs).map_err(de::Error::custom)
+    }
+
+    pub mod option {
+        use std::time::Duration;
+
+        use serde::{Deserialize, Deserializer};
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Duration>, D::Error> {
+            #[derive(Deserialize)]
+            struct Secs(#[serde(with = "super")] Duration);
+
+            Ok(Option::<Secs>::deserialize(deserializer)?.map(|Secs(duration)| duration))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::USER_AGENT;
+
+    use super::*;
+    use crate::test_server::TestServer;
+    use crate::{AuthedClient, ClientManager, ErrorKind, ManagerConfig, MockAuthenticator};
+
+    fn manager(client: ClientConfig) -> ClientManager {
+        ClientManager::builder()
+            .authenticator(Arc::new(MockAuthenticator::default()))
+            .config(ManagerConfig {
+                client,
+                ..ManagerConfig::default()
+            })
+            .build()
+    }
+
+    #[tokio::test]
+    async fn clients_send_the_user_agent() {
+        let server = TestServer::start([]).await;
+        let manager = manager(ClientConfig {
+            user_agent: Some("my-app/1.0".to_string()),
+            ..ClientConfig::default()
+        });
+
+        let client = manager.get_client("key", "secret").await.unwrap();
+        client.get(&server.url).send().await.unwrap();
+
+        assert_eq!(server.requests()[0][USER_AGENT], "my-app/1.0");
+    }
+
+    #[tokio::test]
+    async fn slow_responses_run_into_the_timeout() {
+        let server = TestServer::start([]).await;
+        server.set_delay(Duration::from_secs(5));
+        let manager = manager(ClientConfig {
+            timeout: Some(Duration::from_millis(100)),
+            ..ClientConfig::default()
+        });
+
+        let err = AuthedClient::new(manager, "key", "secret")
+            .get(&server.url)
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(&err, Error::Request(err) if err.is_timeout()),
+            "{err:?}"
+        );
+        assert_eq!(err.kind(), ErrorKind::Transient);
+    }
+}
@@ -0,0 +1,74 @@
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::ApiKey;
+
+/// Everything a client is requested for, from which its cache key is derived
+///
+/// Passed to [`ClientManager::get_client_with_context`](crate::ClientManager::get_client_with_context)
+/// by callers that need several clients for the same api key, e.g. one per tenant.
+#[derive(Debug, Clone)]
+pub struct ClientContext {
+    api_key: ApiKey,
+    tenant: Option<String>,
+    headers: HeaderMap,
+}
+
+impl ClientContext {
+    pub fn new(api_key: impl Into<ApiKey>) -> Self {
+        Self {
+            api_key: api_key.into(),
+            tenant: None,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    pub fn with_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    /// Adds a default header of the client, on top of [`extra_headers`](crate::ManagerConfig::extra_headers)
+    ///
+    /// Replaces an extra header of the same name, and like those must not be an authentication header.
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn api_key(&self) -> &ApiKey {
+        &self.api_key
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+}
+
+/// Derives the key a client is cached under from the context it's requested for
+///
+/// Equal keys must imply interchangeable clients: a context that derives the key of a cached
+/// client is served that client, along with the headers of the context it was created for.
+/// Clients for different api keys, app clients or pools are never shared regardless of the key.
+///
+/// Implemented for closures, the default derives the api key itself.
+pub trait KeyDeriver: Send + Sync {
+    fn derive_key(&self, context: &ClientContext) -> String;
+}
+
+impl<F> KeyDeriver for F
+where
+    F: Fn(&ClientContext) -> String + Send + Sync,
+{
+    fn derive_key(&self, context: &ClientContext) -> String {
+        self(context)
+    }
+}
+
+/// The default [`KeyDeriver`], which keeps a single client per api key
+pub(crate) fn by_api_key(context: &ClientContext) -> String {
+    context.api_key().to_string()
+}
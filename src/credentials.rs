@@ -0,0 +1,184 @@
+use std::fmt;
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+/// Identifies the caller, sent along with every request in the configured
+/// [`api_key_header`](crate::ManagerConfig::api_key_header) by most [`AuthScheme`](crate::AuthScheme)s
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ApiKey(String);
+
+impl ApiKey {
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Self(api_key.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+
+    /// A short, stable identifier of the key that is safe to log
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.0)
+    }
+}
+
+impl AsRef<str> for ApiKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<String> for ApiKey {
+    fn from(api_key: String) -> Self {
+        Self(api_key)
+    }
+}
+
+impl From<&str> for ApiKey {
+    fn from(api_key: &str) -> Self {
+        Self(api_key.to_string())
+    }
+}
+
+/// The secret belonging to an [`ApiKey`]
+///
+/// Deliberately implements neither `Display` nor a revealing `Debug`, so it can't end up
+/// in logs or error messages by accident. The memory holding it is zeroed on drop.
+///
+/// The manager only hashes the secret and passes it on to the [`Authenticator`](crate::Authenticator),
+/// it's retained beyond a single call only by [`keep_warm`](crate::ClientManager::keep_warm),
+/// an [`AuthedClient`](crate::AuthedClient) and, if enabled, [`retain_credentials`](crate::ManagerConfig::retain_credentials),
+/// all of which keep it in an `ApiSecret`.
+#[derive(Clone)]
+pub struct ApiSecret(Zeroizing<String>);
+
+impl ApiSecret {
+    pub fn new(api_secret: impl Into<String>) -> Self {
+        Self(Zeroizing::new(api_secret.into()))
+    }
+
+    /// The raw secret, to be handed only to the authentication backend
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for ApiSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ApiSecret(****)")
+    }
+}
+
+impl From<String> for ApiSecret {
+    fn from(api_secret: String) -> Self {
+        Self::new(api_secret)
+    }
+}
+
+impl From<&str> for ApiSecret {
+    fn from(api_secret: &str) -> Self {
+        Self::new(api_secret)
+    }
+}
+
+/// Replaces every occurrence of `secret` in `message`, e.g. in an error echoing a request
+#[cfg(feature = "tracing")]
+pub(crate) fn redact(message: &str, secret: &ApiSecret) -> String {
+    match secret.expose_secret() {
+        "" => message.to_string(),
+        secret => message.replace(secret, "[redacted]"),
+    }
+}
+
+pub(crate) fn fingerprint(api_key: &str) -> String {
+    let digest = Sha256::digest(api_key);
+
+    digest[..4]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Salted hash of an [`ApiSecret`], to notice a rotated secret without storing the old one
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SecretHash([u8; 32]);
+
+impl fmt::Debug for SecretHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretHash(..)")
+    }
+}
+
+/// Hashes secrets with a random salt, chosen once per manager
+#[derive(Clone)]
+pub(crate) struct SecretHasher {
+    salt: [u8; 16],
+}
+
+impl SecretHasher {
+    pub fn new() -> Self {
+        let mut salt = [0; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+
+        Self { salt }
+    }
+
+    pub fn hash(&self, api_secret: &ApiSecret) -> SecretHash {
+        self.hash_str(api_secret.expose_secret())
+    }
+
+    /// The hasher state holding the secret is zeroed once it's finalized
+    pub fn hash_str(&self, api_secret: &str) -> SecretHash {
+        let digest = Sha256::new()
+            .chain_update(self.salt)
+            .chain_update(api_secret)
+            .finalize();
+
+        SecretHash(digest.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_redacts_the_secret() {
+        let secret = ApiSecret::new("s3cr3t-api-secret");
+
+        assert_eq!(format!("{secret:?}"), "ApiSecret(****)");
+        assert!(!format!("{:?}", Some(&secret)).contains("s3cr3t-api-secret"));
+        assert!(!format!("{:?}", ("key", secret)).contains("s3cr3t-api-secret"));
+    }
+
+    #[test]
+    fn debug_of_the_hash_reveals_nothing() {
+        let hash = SecretHasher::new().hash(&ApiSecret::new("s3cr3t-api-secret"));
+
+        assert_eq!(format!("{hash:?}"), "SecretHash(..)");
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn redact_replaces_every_occurrence() {
+        let secret = ApiSecret::new("s3cr3t");
+
+        assert_eq!(
+            redact("rejected s3cr3t, s3cr3t is wrong", &secret),
+            "rejected [redacted], [redacted] is wrong"
+        );
+        assert_eq!(redact("no secret", &ApiSecret::new("")), "no secret");
+    }
+}
@@ -0,0 +1,505 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::{HeaderName, InvalidHeaderValue};
+
+/// Errors returned when obtaining an authenticated client
+///
+/// Cheap to clone, so that a single failed authentication can be reported
+/// to every caller waiting on it.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum Error {
+    /// The authentication backend rejected the credentials or could not be reached, as told
+    /// apart by the `kind` the [`Authenticator`](crate::Authenticator) classified the failure as
+    #[error("Authentication failed: {source}")]
+    AuthenticationFailed {
+        source: Arc<dyn std::error::Error + Send + Sync>,
+        kind: ErrorKind,
+    },
+
+    /// A single call to the authentication backend took longer than
+    /// [`ManagerConfig::auth_timeout`](crate::ManagerConfig::auth_timeout)
+    #[error("Authentication timed out after {0:?}")]
+    AuthTimeout(Duration),
+
+    /// The api key can't be used as the value of the configured
+    /// [`api_key_header`](crate::ManagerConfig::api_key_header), checked before authenticating
+    #[error("Invalid api key header value: {0}")]
+    InvalidApiKey(#[source] Arc<InvalidHeaderValue>),
+
+    /// The access token returned by the backend can't be used as the `Authorization` header value
+    #[error("Invalid access token header value: {0}")]
+    InvalidAccessToken(#[source] Arc<InvalidHeaderValue>),
+
+    /// One of the configured extra headers would override an authentication header
+    #[error("Extra header {0} conflicts with an authentication header")]
+    ConflictingExtraHeader(HeaderName),
+
+    /// The backend returned a token expiring sooner than [`ManagerConfig::min_lifetime`](crate::ManagerConfig::min_lifetime)
+    #[error("Token lifetime of {0} seconds is below the configured minimum")]
+    InvalidLifetime(i64),
+
+    /// The backend granted a token without some of the scopes requested through
+    /// [`ClientManager::get_client_with_scope`](crate::ClientManager::get_client_with_scope)
+    #[error("Token was not granted the scopes {0:?}")]
+    ScopeNotGranted(Vec<String>),
+
+    /// The circuit breaker is open after repeated failures of the backend, see
+    /// [`CircuitBreakerConfig`](crate::CircuitBreakerConfig)
+    #[error("Authentication backend is failing, the circuit breaker is open")]
+    CircuitOpen,
+
+    /// Authenticating the key again would exceed its [`RateLimitConfig`](crate::RateLimitConfig),
+    /// the next authentication may start after `retry_after`
+    #[error("Authentication rate limit exceeded, retry after {retry_after:?}")]
+    RateLimited { retry_after: Duration },
+
+    /// A blocking function was called from within an async runtime, where it would block a worker thread
+    #[cfg(feature = "blocking")]
+    #[error("Blocking functions must not be called from within an async runtime")]
+    InsideRuntime,
+
+    /// Authenticating with a new secret passed to
+    /// [`ClientManager::rotate_credentials`](crate::ClientManager::rotate_credentials) failed,
+    /// the client authenticated with the previous secret is still cached
+    #[error("Credential rotation failed: {0}")]
+    RotationFailed(#[source] Arc<Error>),
+
+    /// No [`CredentialsProvider`](crate::CredentialsProvider) is registered under the name
+    #[error("No credentials registered as {0:?}")]
+    UnknownCredentials(String),
+
+    /// The [`CredentialsProvider`](crate::CredentialsProvider) failed to supply the credentials
+    #[error("Failed to obtain credentials: {0}")]
+    CredentialsUnavailable(#[source] Arc<dyn std::error::Error + Send + Sync>),
+
+    /// The manager was [shut down](crate::ClientManager::shutdown)
+    #[error("The client manager was shut down")]
+    ShutDown,
+
+    /// Building the `reqwest::Client` failed
+    #[error("Failed to build client: {0}")]
+    ClientBuild(#[source] Arc<reqwest::Error>),
+
+    /// Sending a request through an [`AuthedClient`](crate::AuthedClient) failed
+    #[error("Request failed: {0}")]
+    Request(#[source] Arc<reqwest::Error>),
+
+    /// A field of the [`ManagerConfig`](crate::ManagerConfig) has a value it can't be used with,
+    /// see [`ManagerConfig::validate`](crate::ManagerConfig::validate)
+    #[error("Invalid config field `{field}`: {reason}")]
+    InvalidConfig {
+        field: &'static str,
+        reason: &'static str,
+    },
+}
+
+impl Error {
+    /// Whether the failure is worth retrying, e.g. by calling
+    /// [`refresh_client`](crate::refresh_client) again after a while
+    pub fn is_retryable(&self) -> bool {
+        self.kind() == ErrorKind::Transient
+    }
+
+    /// What caused the failure, see [`ErrorKind`]
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::AuthenticationFailed { kind, .. } => *kind,
+            Self::RotationFailed(err) => err.kind(),
+            Self::AuthTimeout(_)
+            | Self::CircuitOpen
+            | Self::RateLimited { .. }
+            | Self::CredentialsUnavailable(_) => ErrorKind::Transient,
+            Self::Request(err) if crate::auth::is_transient_request(err) => ErrorKind::Transient,
+            Self::ScopeNotGranted(_) => ErrorKind::Rejected,
+            Self::InvalidApiKey(_)
+            | Self::InvalidAccessToken(_)
+            | Self::InvalidLifetime(_)
+            | Self::Request(_) => ErrorKind::Invalid,
+            #[cfg(feature = "blocking")]
+            Self::InsideRuntime => ErrorKind::Config,
+            Self::ConflictingExtraHeader(_)
+            | Self::UnknownCredentials(_)
+            | Self::ClientBuild(_)
+            | Self::InvalidConfig { .. } => ErrorKind::Config,
+            Self::ShutDown => ErrorKind::ShutDown,
+        }
+    }
+
+    pub(crate) fn authentication_failed(err: anyhow::Error, kind: ErrorKind) -> Self {
+        Self::AuthenticationFailed {
+            source: Arc::from(Box::<dyn std::error::Error + Send + Sync>::from(err)),
+            kind,
+        }
+    }
+
+    pub(crate) fn credentials_unavailable(err: anyhow::Error) -> Self {
+        Self::CredentialsUnavailable(Arc::from(Box::<dyn std::error::Error + Send + Sync>::from(
+            err,
+        )))
+    }
+
+    pub(crate) fn invalid_api_key(err: InvalidHeaderValue) -> Self {
+        Self::InvalidApiKey(Arc::new(err))
+    }
+
+    pub(crate) fn invalid_access_token(err: InvalidHeaderValue) -> Self {
+        Self::InvalidAccessToken(Arc::new(err))
+    }
+
+    pub(crate) fn client_build(err: reqwest::Error) -> Self {
+        Self::ClientBuild(Arc::new(err))
+    }
+
+    pub(crate) fn request(err: reqwest::Error) -> Self {
+        Self::Request(Arc::new(err))
+    }
+}
+
+/// A coarse classification of an [`Error`], telling whether retrying makes sense
+///
+/// Authentication failures are classified by the [`Authenticator`](crate::Authenticator),
+/// see [`Authenticator::classify`](crate::Authenticator::classify), all other errors by their cause.
+///
+/// ```
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// use async_trait::async_trait;
+/// use existing_code_challenge::{
+///     ApiSecret, AuthConfig, AuthOutput, Authenticator, ClientManager, ErrorKind, ManagerConfig,
+///     MockClock, RateLimitConfig, RetryPolicy,
+/// };
+///
+/// #[derive(Debug, thiserror::Error)]
+/// #[error("backend is down")]
+/// struct Down;
+///
+/// /// Fails depending on the api key
+/// struct Backend;
+///
+/// #[async_trait]
+/// impl Authenticator for Backend {
+///     async fn authenticate(
+///         &self,
+///         _config: &AuthConfig,
+///         api_key: &str,
+///         _api_secret: &ApiSecret,
+///     ) -> anyhow::Result<AuthOutput> {
+///         match api_key {
+///             "down" => Err(Down.into()),
+///             "denied" => anyhow::bail!("invalid credentials"),
+///             "hangs" => std::future::pending().await,
+///             "short-lived" => Ok(AuthOutput::new("token", 0)),
+///             _ => Ok(AuthOutput::new("token", 3600)),
+///         }
+///     }
+///
+///     fn is_retryable(&self, err: &anyhow::Error) -> bool {
+///         err.is::<Down>()
+///     }
+/// }
+///
+/// let manager = ClientManager::builder()
+///     .authenticator(Arc::new(Backend))
+///     .clock(Arc::new(MockClock::new(1_000)))
+///     .config(ManagerConfig {
+///         retry: RetryPolicy::none(),
+///         auth_timeout: Some(Duration::from_millis(10)),
+///         rate_limit: Some(RateLimitConfig {
+///             burst: 1,
+///             ..RateLimitConfig::default()
+///         }),
+///         ..ManagerConfig::default()
+///     })
+///     .build();
+/// let kind = |api_key: &'static str| {
+///     let manager = manager.clone();
+///     async move { manager.get_client(api_key, "secret").await.unwrap_err().kind() }
+/// };
+///
+/// assert_eq!(kind("down").await, ErrorKind::Transient);
+/// assert_eq!(kind("hangs").await, ErrorKind::Transient);
+/// assert_eq!(kind("denied").await, ErrorKind::Rejected);
+/// assert_eq!(kind("short-lived").await, ErrorKind::Invalid);
+/// assert_eq!(kind("invalid\nkey").await, ErrorKind::Invalid);
+/// // Rate limited after its first, failed authentication
+/// assert_eq!(kind("down").await, ErrorKind::Transient);
+///
+/// let config = ManagerConfig {
+///     max_lifetime: 0,
+///     ..ManagerConfig::default()
+/// };
+/// let err = ClientManager::from_config(config).unwrap_err();
+/// assert_eq!(err.kind(), ErrorKind::Config);
+/// assert!(!err.is_retryable());
+///
+/// manager.shutdown().await;
+/// assert_eq!(kind("key").await, ErrorKind::ShutDown);
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// Retrying later may succeed: timeouts, network errors, server errors, throttling by the
+    /// backend or the [rate limit](crate::ManagerConfig::rate_limit), and an open circuit breaker
+    Transient,
+    /// The backend refused the credentials or didn't grant the requested scopes
+    Rejected,
+    /// A value can't be used, e.g. an api key that isn't a valid header value or a token with a
+    /// lifetime below the configured minimum
+    Invalid,
+    /// The manager is misconfigured or misused
+    Config,
+    /// The manager was [shut down](crate::ClientManager::shutdown)
+    ShutDown,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+
+    use super::*;
+    use crate::{
+        AuthOutput, CircuitBreakerConfig, ClientConfig, ClientManager, ManagerConfig,
+        MockAuthenticator, MockClock, RateLimitConfig, RetryPolicy,
+    };
+
+    fn manager(mock: &MockAuthenticator, config: ManagerConfig) -> ClientManager {
+        ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(MockClock::new(1_000)))
+            .config(ManagerConfig {
+                retry: RetryPolicy::none(),
+                ..config
+            })
+            .build()
+    }
+
+    async fn get_err(manager: &ClientManager, api_key: &str) -> Error {
+        manager.get_client(api_key, "secret").await.unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn rejected_credentials() {
+        let mock = MockAuthenticator::default();
+        mock.push_rejection();
+        let err = get_err(&manager(&mock, ManagerConfig::default()), "key").await;
+
+        assert!(matches!(
+            err,
+            Error::AuthenticationFailed {
+                kind: ErrorKind::Rejected,
+                ..
+            }
+        ));
+        assert!(!err.is_retryable());
+        assert!(std::error::Error::source(&err).is_some());
+        assert!(err.to_string().starts_with("Authentication failed: "));
+    }
+
+    #[tokio::test]
+    async fn transient_failure() {
+        let mock = MockAuthenticator::default();
+        mock.push_transient_error();
+        let err = get_err(&manager(&mock, ManagerConfig::default()), "key").await;
+
+        assert!(matches!(
+            err,
+            Error::AuthenticationFailed {
+                kind: ErrorKind::Transient,
+                ..
+            }
+        ));
+        assert!(err.is_retryable());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timed_out_authentication() {
+        let mock = MockAuthenticator::default();
+        mock.set_delay(Duration::from_secs(60));
+        let config = ManagerConfig {
+            auth_timeout: Some(Duration::from_secs(1)),
+            ..ManagerConfig::default()
+        };
+        let err = get_err(&manager(&mock, config), "key").await;
+
+        assert!(matches!(err, Error::AuthTimeout(timeout) if timeout == Duration::from_secs(1)));
+        assert_eq!(err.kind(), ErrorKind::Transient);
+    }
+
+    #[tokio::test]
+    async fn invalid_api_key() {
+        let mock = MockAuthenticator::default();
+        let err = get_err(&manager(&mock, ManagerConfig::default()), "invalid\nkey").await;
+
+        assert!(matches!(err, Error::InvalidApiKey(_)));
+        assert_eq!(err.kind(), ErrorKind::Invalid);
+        // Rejected before asking the backend
+        mock.assert_calls(0);
+    }
+
+    #[tokio::test]
+    async fn invalid_access_token() {
+        let mock = MockAuthenticator::default();
+        mock.push_token("invalid\ntoken", 3600);
+        let err = get_err(&manager(&mock, ManagerConfig::default()), "key").await;
+
+        assert!(matches!(err, Error::InvalidAccessToken(_)));
+        assert_eq!(err.kind(), ErrorKind::Invalid);
+    }
+
+    #[tokio::test]
+    async fn lifetime_below_minimum() {
+        let mock = MockAuthenticator::default();
+        mock.push_token("token", 30);
+        let config = ManagerConfig {
+            min_lifetime: 60,
+            ..ManagerConfig::default()
+        };
+        let err = get_err(&manager(&mock, config), "key").await;
+
+        assert!(matches!(err, Error::InvalidLifetime(30)));
+        assert_eq!(err.kind(), ErrorKind::Invalid);
+    }
+
+    #[tokio::test]
+    async fn scope_not_granted() {
+        let mock = MockAuthenticator::default();
+        mock.push_output(AuthOutput::new("token", 3600).with_scope(["read"]));
+        let err = manager(&mock, ManagerConfig::default())
+            .get_client_with_scope("key", "secret", ["read", "write"])
+            .await
+            .unwrap_err();
+
+        assert!(matches!(&err, Error::ScopeNotGranted(missing) if missing == &["write"]));
+        assert_eq!(err.kind(), ErrorKind::Rejected);
+    }
+
+    #[tokio::test]
+    async fn conflicting_extra_header() {
+        let mock = MockAuthenticator::default();
+        let mut extra_headers = HeaderMap::new();
+        extra_headers.insert(AUTHORIZATION, HeaderValue::from_static("other"));
+        let config = ManagerConfig {
+            extra_headers,
+            ..ManagerConfig::default()
+        };
+        let err = get_err(&manager(&mock, config), "key").await;
+
+        assert!(matches!(&err, Error::ConflictingExtraHeader(name) if name == AUTHORIZATION));
+        assert_eq!(err.kind(), ErrorKind::Config);
+    }
+
+    #[tokio::test]
+    async fn client_build_failure() {
+        let mock = MockAuthenticator::default();
+        let config = ManagerConfig {
+            client: ClientConfig {
+                proxy: Some("not a proxy url".to_string()),
+                ..ClientConfig::default()
+            },
+            ..ManagerConfig::default()
+        };
+        let err = get_err(&manager(&mock, config), "key").await;
+
+        assert!(matches!(err, Error::ClientBuild(_)));
+        assert_eq!(err.kind(), ErrorKind::Config);
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[tokio::test]
+    async fn rate_limited() {
+        let mock = MockAuthenticator::default();
+        let config = ManagerConfig {
+            rate_limit: Some(RateLimitConfig {
+                burst: 1,
+                ..RateLimitConfig::default()
+            }),
+            ..ManagerConfig::default()
+        };
+        let manager = manager(&mock, config);
+        manager.force_refresh("key", "secret").await.unwrap();
+        let err = manager.force_refresh("key", "secret").await.unwrap_err();
+
+        assert!(matches!(err, Error::RateLimited { .. }));
+        assert!(err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn open_circuit() {
+        let mock = MockAuthenticator::default();
+        mock.fail_next(1);
+        let config = ManagerConfig {
+            circuit_breaker: Some(CircuitBreakerConfig {
+                failure_threshold: 1,
+                ..CircuitBreakerConfig::default()
+            }),
+            ..ManagerConfig::default()
+        };
+        let manager = manager(&mock, config);
+        get_err(&manager, "key").await;
+        let err = get_err(&manager, "key").await;
+
+        assert!(matches!(err, Error::CircuitOpen));
+        assert!(err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn unknown_credentials() {
+        let mock = MockAuthenticator::default();
+        let err = manager(&mock, ManagerConfig::default())
+            .get_client_by_name("missing")
+            .await
+            .unwrap_err();
+
+        assert!(matches!(&err, Error::UnknownCredentials(name) if name == "missing"));
+        assert_eq!(err.kind(), ErrorKind::Config);
+    }
+
+    #[tokio::test]
+    async fn failed_rotation_keeps_the_kind() {
+        let mock = MockAuthenticator::default();
+        mock.push_rejection();
+        let err = manager(&mock, ManagerConfig::default())
+            .rotate_credentials("key", "new secret")
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, Error::RotationFailed(_)));
+        assert_eq!(err.kind(), ErrorKind::Rejected);
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        let config = ManagerConfig {
+            refresh_skew: -1,
+            ..ManagerConfig::default()
+        };
+        let err = ClientManager::from_config(config).unwrap_err();
+
+        assert!(matches!(
+            err,
+            Error::InvalidConfig {
+                field: "refresh_skew",
+                ..
+            }
+        ));
+        assert_eq!(err.kind(), ErrorKind::Config);
+    }
+
+    #[tokio::test]
+    async fn shut_down() {
+        let mock = MockAuthenticator::default();
+        let manager = manager(&mock, ManagerConfig::default());
+        manager.shutdown().await;
+        let err = get_err(&manager, "key").await;
+
+        assert!(matches!(err, Error::ShutDown));
+        assert_eq!(err.kind(), ErrorKind::ShutDown);
+    }
+}
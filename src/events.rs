@@ -0,0 +1,42 @@
+use tokio::sync::broadcast;
+
+use crate::{CacheKey, FailureKind};
+
+/// How many events a subscriber may fall behind before it misses the oldest ones
+const EVENT_CAPACITY: usize = 64;
+
+/// Something that happened to a cached client, see [`ClientManager::subscribe`](crate::ClientManager::subscribe)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenEvent {
+    /// A new token was cached for `key`, either authenticated or restored from the store
+    Refreshed { key: CacheKey, expires_at: i64 },
+    /// Authenticating `key` failed, the cached client, if any, was left in place
+    RefreshFailed { key: CacheKey, kind: FailureKind },
+    /// The client for `key` was removed from the cache, either evicted or invalidated
+    Evicted { key: CacheKey },
+}
+
+/// Publishes events to every subscriber without ever waiting for them
+#[derive(Debug)]
+pub(crate) struct Events(broadcast::Sender<TokenEvent>);
+
+impl Events {
+    pub fn new() -> Self {
+        Self(broadcast::channel(EVENT_CAPACITY).0)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<TokenEvent> {
+        self.0.subscribe()
+    }
+
+    pub fn emit(&self, event: TokenEvent) {
+        // Only fails if there are no subscribers
+        let _ = self.0.send(event);
+    }
+
+    pub fn evicted(&self, keys: impl IntoIterator<Item = CacheKey>) {
+        for key in keys {
+            self.emit(TokenEvent::Evicted { key });
+        }
+    }
+}
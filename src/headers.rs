@@ -0,0 +1,297 @@
+use std::fmt;
+use std::sync::Arc;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, InvalidHeaderValue, AUTHORIZATION};
+
+use crate::{Error, ManagerConfig};
+
+pub(crate) const X_API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+
+/// How a client presents its access token
+///
+/// All values carrying the token are marked sensitive, so that they're redacted from debug output.
+/// The api key is sent in the [`api_key_header`](ManagerConfig::api_key_header), `X-Api-Key` by
+/// default.
+///
+/// ```
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), existing_code_challenge::Error> {
+/// use existing_code_challenge::{AuthConfig, AuthScheme, ClientManager, ClientMode, ManagerConfig};
+/// use reqwest::header::AUTHORIZATION;
+/// use reqwest::Method;
+///
+/// let manager = ClientManager::new(ManagerConfig {
+///     auth: AuthConfig::new("client", "pool"),
+///     auth_scheme: AuthScheme::Raw,
+///     // Puts the headers on every request instead of the client, where they can't be inspected
+///     client_mode: ClientMode::Shared,
+///     ..ManagerConfig::default()
+/// });
+///
+/// let request = manager
+///     .authorized_request(Method::GET, "https://example.com", "key", "secret")
+///     .await?
+///     .build()
+///     .unwrap();
+/// let authorization = &request.headers()[AUTHORIZATION];
+/// assert_eq!(authorization, "client:pool:key");
+/// assert!(authorization.is_sensitive());
+/// assert_eq!(request.headers()["x-api-key"], "key");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum AuthScheme {
+    /// `Authorization: Bearer <token>` along with the api key
+    #[default]
+    Bearer,
+    /// `Authorization: <prefix> <token>` along with the api key, e.g. with the prefix `Token`
+    Prefix(String),
+    /// `Authorization: <token>` without a prefix, along with the api key
+    Raw,
+    /// `Authorization` set to the value built from the token by a function, along with the api key
+    ///
+    /// Values that aren't valid header values fail with [`Error::InvalidAccessToken`].
+    Format(TokenFormat),
+    /// Only the bare token in the given header, without the api key
+    Header(HeaderName),
+    /// Headers built from the access token and api key by a function
+    Custom(CustomScheme),
+}
+
+/// Builds the authentication headers from an access token and api key, see [`AuthScheme::Custom`]
+///
+/// Schemes are only equal to their clones.
+#[derive(Clone)]
+pub struct CustomScheme(Arc<SchemeFn>);
+
+type SchemeFn = dyn Fn(&str, &str) -> Result<HeaderMap, InvalidHeaderValue> + Send + Sync;
+
+impl CustomScheme {
+    pub fn new(
+        scheme: impl Fn(&str, &str) -> Result<HeaderMap, InvalidHeaderValue> + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(scheme))
+    }
+}
+
+impl fmt::Debug for CustomScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CustomScheme(..)")
+    }
+}
+
+impl PartialEq for CustomScheme {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CustomScheme {}
+
+/// Builds the `Authorization` value from an access token, see [`AuthScheme::Format`]
+///
+/// Formats are only equal to their clones.
+#[derive(Clone)]
+pub struct TokenFormat(Arc<dyn Fn(&str) -> String + Send + Sync>);
+
+impl TokenFormat {
+    pub fn new(format: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        Self(Arc::new(format))
+    }
+}
+
+impl fmt::Debug for TokenFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TokenFormat(..)")
+    }
+}
+
+impl PartialEq for TokenFormat {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for TokenFormat {}
+
+/// Fails unless `api_key` can be sent as a header by `scheme`, which the custom scheme is
+/// trusted to handle itself
+///
+/// Checked before authenticating, as a key that can't be sent never results in a usable client.
+pub(crate) fn validate_api_key(config: &ManagerConfig, api_key: &str) -> Result<(), Error> {
+    match (sends_api_key(&config.auth_scheme), &config.api_key_header) {
+        (true, Some(_)) => api_key_value(api_key).map(drop),
+        _ => Ok(()),
+    }
+}
+
+/// Whether `scheme` sends the api key in the [`api_key_header`](ManagerConfig::api_key_header)
+pub(crate) fn sends_api_key(scheme: &AuthScheme) -> bool {
+    match scheme {
+        AuthScheme::Bearer | AuthScheme::Prefix(_) | AuthScheme::Raw | AuthScheme::Format(_) => {
+            true
+        }
+        AuthScheme::Header(_) | AuthScheme::Custom(_) => false,
+    }
+}
+
+/// The default headers of a client authorized with `access_token`: `extra` plus the
+/// authentication headers of the configured scheme, none of which `extra` may contain
+pub(crate) fn auth_headers(
+    config: &ManagerConfig,
+    access_token: &str,
+    api_key: &str,
+    extra: HeaderMap,
+) -> Result<HeaderMap, Error> {
+    let mut auth = HeaderMap::new();
+    match &config.auth_scheme {
+        AuthScheme::Bearer => {
+            auth.insert(
+                AUTHORIZATION,
+                token_value(&format!("Bearer {access_token}"))?,
+            );
+        }
+        AuthScheme::Prefix(prefix) => {
+            auth.insert(
+                AUTHORIZATION,
+                token_value(&format!("{prefix} {access_token}"))?,
+            );
+        }
+        AuthScheme::Raw => {
+            auth.insert(AUTHORIZATION, token_value(access_token)?);
+        }
+        AuthScheme::Format(format) => {
+            auth.insert(AUTHORIZATION, token_value(&(format.0)(access_token))?);
+        }
+        AuthScheme::Header(name) => {
+            auth.insert(name.clone(), token_value(access_token)?);
+        }
+        AuthScheme::Custom(scheme) => {
+            auth = (scheme.0)(access_token, api_key).map_err(Error::invalid_access_token)?;
+            for value in auth.values_mut() {
+                value.set_sensitive(true);
+            }
+        }
+    }
+    if let Some(name) = config
+        .api_key_header
+        .as_ref()
+        .filter(|_| sends_api_key(&config.auth_scheme))
+    {
+        auth.insert(name.clone(), api_key_value(api_key)?);
+    }
+
+    let mut headers = extra;
+    for name in auth.keys() {
+        if headers.contains_key(name) {
+            return Err(Error::ConflictingExtraHeader(name.clone()));
+        }
+    }
+    headers.extend(auth);
+
+    Ok(headers)
+}
+
+fn token_value(value: &str) -> Result<HeaderValue, Error> {
+    let mut value = HeaderValue::from_str(value).map_err(Error::invalid_access_token)?;
+    value.set_sensitive(true);
+
+    Ok(value)
+}
+
+fn api_key_value(api_key: &str) -> Result<HeaderValue, Error> {
+    HeaderValue::from_str(api_key).map_err(Error::invalid_api_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::TestServer;
+    use crate::{ClientManager, MockAuthenticator};
+
+    /// The headers a client authorized with the `mock:key:1` token sends, along with the
+    /// authentication headers it was built with
+    async fn sent(auth_scheme: AuthScheme) -> (HeaderMap, HeaderMap) {
+        let config = ManagerConfig {
+            auth_scheme,
+            ..ManagerConfig::default()
+        };
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(MockAuthenticator::default()))
+            .config(config.clone())
+            .build();
+        let server = TestServer::start([]).await;
+
+        let client = manager.get_client("key", "secret").await.unwrap();
+        client.get(&server.url).send().await.unwrap();
+
+        let built = auth_headers(&config, "mock:key:1", "key", HeaderMap::new()).unwrap();
+        (server.requests().remove(0), built)
+    }
+
+    #[tokio::test]
+    async fn bearer() {
+        let (sent, built) = sent(AuthScheme::Bearer).await;
+
+        assert_eq!(sent[AUTHORIZATION], "Bearer mock:key:1");
+        assert_eq!(sent[X_API_KEY], "key");
+        assert!(built[AUTHORIZATION].is_sensitive());
+    }
+
+    #[tokio::test]
+    async fn prefix() {
+        let (sent, built) = sent(AuthScheme::Prefix("Token".to_string())).await;
+
+        assert_eq!(sent[AUTHORIZATION], "Token mock:key:1");
+        assert_eq!(sent[X_API_KEY], "key");
+        assert!(built[AUTHORIZATION].is_sensitive());
+    }
+
+    #[tokio::test]
+    async fn raw() {
+        let (sent, built) = sent(AuthScheme::Raw).await;
+
+        assert_eq!(sent[AUTHORIZATION], "mock:key:1");
+        assert_eq!(sent[X_API_KEY], "key");
+        assert!(built[AUTHORIZATION].is_sensitive());
+    }
+
+    #[tokio::test]
+    async fn format() {
+        let format = TokenFormat::new(|token| format!("Token token=\"{token}\""));
+        let (sent, built) = sent(AuthScheme::Format(format)).await;
+
+        assert_eq!(sent[AUTHORIZATION], "Token token=\"mock:key:1\"");
+        assert_eq!(sent[X_API_KEY], "key");
+        assert!(built[AUTHORIZATION].is_sensitive());
+    }
+
+    #[tokio::test]
+    async fn header() {
+        let name = HeaderName::from_static("x-auth-token");
+        let (sent, built) = sent(AuthScheme::Header(name.clone())).await;
+
+        assert_eq!(sent[&name], "mock:key:1");
+        assert!(!sent.contains_key(AUTHORIZATION));
+        assert!(!sent.contains_key(X_API_KEY));
+        assert!(built[&name].is_sensitive());
+    }
+
+    #[tokio::test]
+    async fn custom() {
+        let scheme = CustomScheme::new(|token, api_key| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-token", HeaderValue::from_str(token)?);
+            headers.insert("x-key", HeaderValue::from_str(api_key)?);
+            Ok(headers)
+        });
+        let (sent, built) = sent(AuthScheme::Custom(scheme)).await;
+
+        assert_eq!(sent["x-token"], "mock:key:1");
+        assert_eq!(sent["x-key"], "key");
+        assert!(!sent.contains_key(AUTHORIZATION));
+        assert!(!sent.contains_key(X_API_KEY));
+        assert!(built["x-token"].is_sensitive());
+    }
+}
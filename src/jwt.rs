@@ -0,0 +1,98 @@
+//! Reading the expiration of JWT access tokens, without verifying them
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+
+/// The `exp` claim of `token`, or `None` if it's not a JWT or has no numeric `exp` claim
+pub(crate) fn expiration(token: &str) -> Option<i64> {
+    let mut parts = token.split('.');
+    let (_header, payload, _signature) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+
+    // Some issuers pad the segments despite the spec
+    let payload = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+
+    let exp = claims.get("exp")?;
+    exp.as_i64().or_else(|| exp.as_f64().map(|exp| exp as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{ClientManager, MockAuthenticator, MockClock};
+
+    fn jwt(claims: &str) -> String {
+        let encode = |segment: &str| URL_SAFE_NO_PAD.encode(segment);
+
+        format!(
+            "{}.{}.signature",
+            encode(r#"{"alg":"HS256","typ":"JWT"}"#),
+            encode(claims)
+        )
+    }
+
+    #[test]
+    fn reads_the_exp_claim() {
+        assert_eq!(expiration(&jwt(r#"{"exp":4600}"#)), Some(4_600));
+        assert_eq!(expiration(&jwt(r#"{"exp":4600.5}"#)), Some(4_600));
+    }
+
+    #[test]
+    fn padded_segments_are_accepted() {
+        let token = jwt(r#"{"exp":4600}"#).replacen('.', "==.", 2);
+
+        assert_eq!(expiration(&token), Some(4_600));
+    }
+
+    #[test]
+    fn missing_exp() {
+        assert_eq!(expiration(&jwt(r#"{"sub":"user"}"#)), None);
+        assert_eq!(expiration(&jwt(r#"{"exp":"4600"}"#)), None);
+    }
+
+    #[test]
+    fn non_jwt_tokens() {
+        assert_eq!(expiration("opaque-token"), None);
+        assert_eq!(expiration("a.b"), None);
+        assert_eq!(expiration("a.b.c.d"), None);
+        assert_eq!(expiration("header.not base64!.signature"), None);
+        assert_eq!(
+            expiration(&format!("h.{}.s", URL_SAFE_NO_PAD.encode("not json"))),
+            None
+        );
+    }
+
+    /// When the token issued by a backend answering with `expires_in` expires, at 1000
+    async fn expires_at(access_token: &str, expires_in: i64) -> i64 {
+        let mock = MockAuthenticator::default();
+        mock.push_token(access_token, expires_in);
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock))
+            .clock(Arc::new(MockClock::new(1_000)))
+            .build();
+
+        manager.get_client("key", "secret").await.unwrap();
+        manager.expires_at("key").unwrap().timestamp()
+    }
+
+    #[tokio::test]
+    async fn earlier_exp_wins() {
+        assert_eq!(expires_at(&jwt(r#"{"exp":2800}"#), 3_600).await, 2_800);
+    }
+
+    #[tokio::test]
+    async fn later_exp_is_capped_by_expires_in() {
+        assert_eq!(expires_at(&jwt(r#"{"exp":9000}"#), 3_600).await, 4_600);
+    }
+
+    #[tokio::test]
+    async fn expires_in_applies_without_exp() {
+        assert_eq!(expires_at(&jwt(r#"{"sub":"user"}"#), 3_600).await, 4_600);
+        assert_eq!(expires_at("opaque-token", 3_600).await, 4_600);
+    }
+}
@@ -5,99 +5,186 @@
 //! - We want to design an internal library for handling this process
 //! - Assume that constructing a `reqwest::Client` is expensive and we don't want to recreate it everytime we need a client
 //! - The API of this library is not set in stone, feel free to change almost any aspect of this code
-
-use std::collections::HashMap;
-use std::fmt::Debug;
+//!
+//! Cargo features:
+//! - `tracing`: spans and events for every authentication, identifying keys only by their
+//!   [fingerprint](ApiKey::fingerprint) and never recording secrets or tokens
+//! - `blocking`: the `blocking` module for callers without an async runtime
+//! - `jwt`: takes the expiration of JWT access tokens from their `exp` claim if it's earlier than `expires_in`
+//! - `middleware`: an `AuthMiddleware` authorizing the requests of a `reqwest-middleware` client
+//! - `oauth2`: an `OAuth2Authenticator` for token endpoints supporting the client credentials grant
+//! - `persist`: a `FileTokenStore` keeping tokens across restarts
+//! - `serde`: `Deserialize` for the `ManagerConfig`, and `Serialize` for the `CacheEntryInfo` of a
+//!   snapshot of the cache
+//! - `testing`: a `MockAuthenticator` for tests of code depending on this crate
+//! - `tower`: an `AuthLayer` authorizing the requests of a `tower` service
 
 use once_cell::sync::Lazy;
-use reqwest::header::HeaderValue;
 use reqwest::Client;
-use tokio::sync::Mutex;
-
-// Public auth input data
-const CLIENT_ID: &str = "1bpd19lcr33qvg5cr3oi79rdap";
-const POOL_ID: &str = "us-west-2_iLmIggsiy";
 
-#[derive(Debug)]
-struct ExpiringClient {
-    client: Client,
-    expiration_time: i64,
+/// Emits a `tracing` event at the given level, if the `tracing` feature is enabled
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($arg)+);
+    };
 }
 
-static CLIENTS: Lazy<Mutex<HashMap<String, ExpiringClient>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
+mod auth;
+mod authed;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod breaker;
+mod builder;
+mod cache;
+mod clock;
+mod config;
+mod context;
+mod credentials;
+mod error;
+mod events;
+mod headers;
+#[cfg(feature = "jwt")]
+mod jwt;
+mod limiter;
+mod manager;
+mod metrics;
+#[cfg(feature = "middleware")]
+mod middleware;
+#[cfg(any(test, feature = "testing"))]
+mod mock;
+#[cfg(feature = "oauth2")]
+mod oauth2;
+mod provider;
+mod retry;
+#[cfg(feature = "tower")]
+mod service;
+mod store;
+#[cfg(test)]
+mod test_server;
+mod token;
+
+pub use self::auth::{is_transient, AuthOutput, Authenticator, StaticAuthenticator};
+pub use self::authed::AuthedClient;
+#[cfg(feature = "blocking")]
+pub use self::blocking::refresh_client_blocking;
+pub use self::breaker::CircuitState;
+pub use self::builder::ClientManagerBuilder;
+pub use self::cache::{CacheEntryInfo, CacheKey, CacheOutcome, CacheStats};
+pub use self::clock::{Clock, MockClock, SystemClock};
+pub use self::config::{
+    AuthConfig, BuilderHook, CircuitBreakerConfig, ClientConfig, ClientMode, KeepWarmConfig,
+    LogLevel, ManagerConfig, RateLimitConfig, ReplayPolicy,
+};
+pub use self::context::{ClientContext, KeyDeriver};
+pub use self::credentials::{ApiKey, ApiSecret};
+pub use self::error::{Error, ErrorKind};
+pub use self::events::TokenEvent;
+pub use self::headers::{AuthScheme, CustomScheme, TokenFormat};
+pub use self::limiter::RateLimitState;
+pub use self::manager::ClientManager;
+pub use self::metrics::{AtomicMetrics, FailureKind, Metrics, NoopMetrics};
+#[cfg(feature = "middleware")]
+pub use self::middleware::AuthMiddleware;
+#[cfg(any(test, feature = "testing"))]
+pub use self::mock::MockAuthenticator;
+#[cfg(feature = "oauth2")]
+pub use self::oauth2::{OAuth2Authenticator, OAuth2Error};
+pub use self::provider::{CredentialsProvider, EnvCredentials, StaticCredentials};
+pub use self::retry::{parse_retry_after, RetryPolicy};
+#[cfg(feature = "tower")]
+pub use self::service::{AuthLayer, AuthService, AuthServiceError};
+#[cfg(feature = "persist")]
+pub use self::store::FileTokenStore;
+pub use self::store::{MemoryTokenStore, StoredToken, TokenStore};
+pub use self::token::AccessToken;
+
+static DEFAULT_MANAGER: Lazy<ClientManager> = Lazy::new(ClientManager::default);
+
+/// Returns an authenticated client from a process-wide [`ClientManager`] using the default config
+///
+/// Unlike the functions taking `impl Into<ApiKey>` and `impl Into<ApiSecret>`, this only borrows
+/// the credentials, so that serving a cached client doesn't allocate, see
+/// [`ClientManager::get_client_ref`]. An [`ApiKey`] can be passed as is, an [`ApiSecret`] as
+/// [`expose_secret`](ApiSecret::expose_secret).
+pub async fn refresh_client(
+    api_key: impl AsRef<str>,
+    api_secret: impl AsRef<str>,
+) -> Result<Client, Error> {
+    let (client, _) = refresh_client_detailed(api_key, api_secret).await?;
 
-pub async fn refresh_client(api_key: String, api_secret: String) -> Result<Client, String> {
-    let now = chrono::Utc::now().timestamp();
+    Ok(client)
+}
 
-    let mut clients = CLIENTS.lock().await;
-    if let Some(client) = clients.get(&api_key) {
-        if now < client.expiration_time {
-            return Ok(client.client.clone());
-        }
-    }
+/// Like [`refresh_client`], but also tells whether the client was cached or authenticated
+///
+/// Borrows the credentials for the same reason as `refresh_client`.
+pub async fn refresh_client_detailed(
+    api_key: impl AsRef<str>,
+    api_secret: impl AsRef<str>,
+) -> Result<(Client, CacheOutcome), Error> {
+    DEFAULT_MANAGER
+        .get_client_detailed(api_key.as_ref(), api_secret.as_ref())
+        .await
+}
 
-    let res = auth::authenticate(CLIENT_ID, POOL_ID, &api_key, &api_secret)
+/// Like [`refresh_client`], but also returns the unix timestamp at which the client's token expires
+pub async fn refresh_client_with_expiry(
+    api_key: impl Into<ApiKey>,
+    api_secret: impl Into<ApiSecret>,
+) -> Result<(Client, i64), Error> {
+    DEFAULT_MANAGER
+        .get_client_with_expiry(api_key, api_secret)
         .await
-        .map_err(|err| format!("Authentication failed: {err}"))?;
-
-    let access_token = res.access_token();
-
-    let mut auth_value = HeaderValue::from_str(&format!("Bearer {access_token}"))
-        .map_err(|err| format!("Invalid header value: {err}"))?;
-    auth_value.set_sensitive(true);
-
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert(reqwest::header::AUTHORIZATION, auth_value);
-    headers.insert(
-        "X-Api-Key",
-        HeaderValue::from_str(&api_key).map_err(|err| format!("Invalid header value: {err}"))?,
-    );
-
-    let client = Client::builder()
-        .default_headers(headers)
-        .build()
-        .map_err(|err| format!("Failed to build client: {err}"))?;
-
-    clients.insert(
-        api_key.to_string(),
-        ExpiringClient {
-            client: client.clone(),
-            expiration_time: now + res.expires_in(),
-        },
-    );
+}
 
-    Ok(client)
+/// Like [`refresh_client`], but for a token granted at least `scopes`, see
+/// [`ClientManager::get_client_with_scope`]
+pub async fn refresh_client_with_scope(
+    api_key: impl Into<ApiKey>,
+    api_secret: impl Into<ApiSecret>,
+    scopes: impl IntoIterator<Item = impl Into<String>>,
+) -> Result<Client, Error> {
+    DEFAULT_MANAGER
+        .get_client_with_scope(api_key, api_secret, scopes)
+        .await
 }
 
-/// A placeholder auth implementation
-mod auth {
-    pub struct AuthOutput {
-        access_token: String,
-        expires_in: i64,
-    }
+/// Returns the access token of the client [`refresh_client`] returns for `api_key`
+pub async fn get_token(
+    api_key: impl Into<ApiKey>,
+    api_secret: impl Into<ApiSecret>,
+) -> Result<AccessToken, Error> {
+    DEFAULT_MANAGER.get_token(api_key, api_secret).await
+}
 
-    impl AuthOutput {
-        pub fn access_token(&self) -> &str {
-            &self.access_token
-        }
+/// Authenticates again regardless of the client cached by [`refresh_client`] and replaces it
+pub async fn force_refresh(
+    api_key: impl Into<ApiKey>,
+    api_secret: impl Into<ApiSecret>,
+) -> Result<Client, Error> {
+    DEFAULT_MANAGER.force_refresh(api_key, api_secret).await
+}
 
-        pub fn expires_in(&self) -> i64 {
-            self.expires_in
-        }
-    }
+/// Returns the client cached by [`refresh_client`] for `api_key` if it's still fresh,
+/// without ever authenticating
+pub fn get_cached_client(api_key: &str) -> Option<Client> {
+    DEFAULT_MANAGER.get_cached_client(api_key)
+}
 
-    pub async fn authenticate(
-        client_id: &str,
-        pool_id: &str,
-        api_key: &str,
-        password: &str,
-    ) -> anyhow::Result<AuthOutput> {
-        Ok(AuthOutput {
-            access_token: format!("{client_id}:{pool_id}:{api_key}:{password}"),
-            expires_in: 3600,
-        })
-    }
+/// Whether [`get_cached_client`] would return a client for `api_key`
+pub fn is_cached(api_key: &str) -> bool {
+    DEFAULT_MANAGER.is_cached(api_key)
+}
+
+/// Removes the client cached by [`refresh_client`] for `api_key`, returning whether there was one
+pub async fn invalidate(api_key: &str) -> bool {
+    DEFAULT_MANAGER.invalidate(api_key).await
+}
+
+/// Removes all clients cached by [`refresh_client`]
+pub async fn invalidate_all() {
+    DEFAULT_MANAGER.invalidate_all().await
 }
 
 #[cfg(ignore)]
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::{CacheKey, Error, RateLimitConfig};
+
+/// The rate limit of a key's authentications, see [`RateLimitConfig`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitState {
+    /// How many authentications may start right away
+    pub available: u32,
+    /// How long until the next authentication may start, zero if one may start right away
+    pub retry_after: Duration,
+}
+
+/// A token bucket per key, refilled as the manager's clock advances
+///
+/// Authentications beyond the limit that wait take their token up front, leaving the bucket in
+/// debt, so that the authentications waiting at the same time are spaced out by the refill rate.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<HashMap<CacheKey, Bucket>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    /// Negative while authentications are waiting for their token
+    tokens: f64,
+    refilled_at: i64,
+}
+
+impl Bucket {
+    fn refill(&mut self, config: &RateLimitConfig, now: i64) {
+        let elapsed = (now - self.refilled_at).max(0) as f64;
+        self.tokens = (self.tokens + elapsed * config.per_second).min(f64::from(config.burst));
+        self.refilled_at = self.refilled_at.max(now);
+    }
+
+    fn retry_after(&self, config: &RateLimitConfig) -> Duration {
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+
+        Duration::try_from_secs_f64((1.0 - self.tokens) / config.per_second)
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token of `key` at `now`, returning how long to wait before authenticating
+    pub fn acquire(&self, key: &CacheKey, now: i64) -> Result<Duration, Error> {
+        let config = &self.config;
+        let mut buckets = self.buckets.lock().unwrap();
+        // Full buckets behave like missing ones, dropping them keeps the map to the keys in use
+        buckets.retain(|_, bucket| {
+            bucket.refill(config, now);
+            bucket.tokens < f64::from(config.burst)
+        });
+
+        let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+            tokens: f64::from(config.burst),
+            refilled_at: now,
+        });
+        let wait = bucket.retry_after(config);
+        if wait > config.max_wait {
+            return Err(Error::RateLimited { retry_after: wait });
+        }
+        bucket.tokens -= 1.0;
+
+        Ok(wait)
+    }
+
+    pub fn state(&self, key: &CacheKey, now: i64) -> RateLimitState {
+        let config = &self.config;
+        let Some(mut bucket) = self.buckets.lock().unwrap().get(key).copied() else {
+            return RateLimitState {
+                available: config.burst,
+                retry_after: Duration::ZERO,
+            };
+        };
+        bucket.refill(config, now);
+
+        RateLimitState {
+            available: bucket.tokens.max(0.0) as u32,
+            retry_after: bucket.retry_after(config),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use reqwest::header::HeaderMap;
+
+    use super::*;
+    use crate::{
+        AuthConfig, ClientManager, ManagerConfig, MockAuthenticator, MockClock, RetryPolicy,
+    };
+
+    fn key() -> CacheKey {
+        CacheKey::new(
+            AuthConfig::default(),
+            "key".to_string(),
+            "key".to_string(),
+            HeaderMap::new(),
+        )
+    }
+
+    fn limiter(max_wait: Duration) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            burst: 3,
+            per_second: 0.5,
+            max_wait,
+        })
+    }
+
+    #[test]
+    fn bursts_are_limited() {
+        let limiter = limiter(Duration::ZERO);
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire(&key(), 1_000).unwrap(), Duration::ZERO);
+        }
+        assert_eq!(
+            limiter.state(&key(), 1_000),
+            RateLimitState {
+                available: 0,
+                retry_after: Duration::from_secs(2),
+            }
+        );
+
+        let err = limiter.acquire(&key(), 1_000).unwrap_err();
+        assert!(
+            matches!(err, Error::RateLimited { retry_after } if retry_after == Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn buckets_refill_up_to_the_burst() {
+        let limiter = limiter(Duration::ZERO);
+        for _ in 0..3 {
+            limiter.acquire(&key(), 1_000).unwrap();
+        }
+
+        assert_eq!(limiter.state(&key(), 1_002).available, 1);
+        // Half a token doesn't count
+        assert_eq!(limiter.state(&key(), 1_003).available, 1);
+        assert_eq!(
+            limiter.state(&key(), 2_000),
+            RateLimitState {
+                available: 3,
+                retry_after: Duration::ZERO,
+            }
+        );
+    }
+
+    #[test]
+    fn waiting_authentications_are_spaced_out() {
+        let limiter = limiter(Duration::from_secs(10));
+        for _ in 0..3 {
+            limiter.acquire(&key(), 1_000).unwrap();
+        }
+
+        assert_eq!(
+            limiter.acquire(&key(), 1_000).unwrap(),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            limiter.acquire(&key(), 1_000).unwrap(),
+            Duration::from_secs(4)
+        );
+        assert_eq!(limiter.state(&key(), 1_000).available, 0);
+        assert_eq!(
+            limiter.state(&key(), 1_000).retry_after,
+            Duration::from_secs(6)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn authentications_beyond_the_burst_wait_for_their_token() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(clock.clone()))
+            .config(ManagerConfig {
+                retry: RetryPolicy::none(),
+                rate_limit: Some(RateLimitConfig {
+                    burst: 1,
+                    per_second: 1.0,
+                    max_wait: Duration::from_secs(5),
+                }),
+                ..ManagerConfig::default()
+            })
+            .build();
+
+        let start = tokio::time::Instant::now();
+        manager.force_refresh("key", "secret").await.unwrap();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        manager.force_refresh("key", "secret").await.unwrap();
+
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+        mock.assert_calls(2);
+        // The token taken up front is owed until the wall clock catches up with the wait
+        clock.advance(1);
+        assert_eq!(
+            manager.rate_limit_state("key"),
+            Some(RateLimitState {
+                available: 0,
+                retry_after: Duration::from_secs(1),
+            })
+        );
+    }
+}
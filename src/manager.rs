@@ -0,0 +1,4012 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::task::Poll;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use reqwest::header::HeaderMap;
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Response};
+use tokio::sync::{broadcast, watch, OnceCell, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::breaker::CircuitBreaker;
+use crate::cache::{
+    is_fresh, normalize_scopes, offset, BorrowedKey, Cache, CacheEntryInfo, CacheKey, CacheOutcome,
+    CacheStats, ExpiringClient, JitterRng, KeyView, RefreshToken, TokenEntry,
+};
+use crate::context::by_api_key;
+use crate::credentials::{SecretHash, SecretHasher};
+use crate::events::Events;
+use crate::headers::{auth_headers, validate_api_key};
+use crate::limiter::RateLimiter;
+use crate::{
+    AccessToken, ApiKey, ApiSecret, AuthConfig, AuthOutput, Authenticator, CircuitState,
+    ClientContext, ClientManagerBuilder, ClientMode, Clock, CredentialsProvider, Error, ErrorKind,
+    FailureKind, KeepWarmConfig, KeyDeriver, ManagerConfig, Metrics, NoopMetrics, RateLimitState,
+    StaticAuthenticator, SystemClock, TokenEvent, TokenStore,
+};
+
+impl ExpiringClient {
+    fn authorized(&self) -> Authorized {
+        Authorized {
+            client: self.client.clone(),
+            token: self.token.clone(),
+        }
+    }
+}
+
+/// Owns a cache of authenticated clients, keyed by api key
+///
+/// The key also includes the app client id and pool id the client was authenticated against,
+/// which is the configured [`AuthConfig`] unless requested through
+/// [`get_client_for`](Self::get_client_for). Methods taking only an api key refer to the configured one.
+/// Several clients can be kept for the same api key with a [`KeyDeriver`], see
+/// [`get_client_with_context`](Self::get_client_with_context).
+///
+/// Cloning is cheap and all clones share the same cache, so a single manager
+/// can be handed out to many tasks.
+///
+/// # Cancellation
+///
+/// Every future returned by the manager can be dropped at any point, e.g. by a timeout around a
+/// request handler. The cache is only ever updated under a lock that isn't held across an
+/// `.await`, so an authentication that's dropped caches either its whole result or nothing.
+/// Callers waiting for it are not affected: one of them authenticates in its place, and calls
+/// made later authenticate as if it never happened. A dropped authentication may still have
+/// used up a token of the [`rate_limit`](ManagerConfig::rate_limit), and if it was probing a
+/// [circuit breaker](ManagerConfig::circuit_breaker) another probe is only let through after
+/// the cooldown.
+///
+/// ```
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> Result<(), existing_code_challenge::Error> {
+/// use std::sync::atomic::{AtomicBool, Ordering};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// use async_trait::async_trait;
+/// use existing_code_challenge::{ApiSecret, AuthConfig, AuthOutput, Authenticator, ClientManager};
+///
+/// /// Never answers the first call
+/// #[derive(Default)]
+/// struct HangsOnce(AtomicBool);
+///
+/// #[async_trait]
+/// impl Authenticator for HangsOnce {
+///     async fn authenticate(
+///         &self,
+///         _config: &AuthConfig,
+///         _api_key: &str,
+///         _api_secret: &ApiSecret,
+///     ) -> anyhow::Result<AuthOutput> {
+///         if !self.0.swap(true, Ordering::SeqCst) {
+///             std::future::pending::<()>().await;
+///         }
+///
+///         Ok(AuthOutput::new("token", 3600))
+///     }
+/// }
+///
+/// let manager = ClientManager::builder()
+///     .authenticator(Arc::new(HangsOnce::default()))
+///     .build();
+///
+/// let first = manager.get_client("key", "secret");
+/// let waiting = tokio::spawn({
+///     let manager = manager.clone();
+///     async move { manager.get_client("key", "secret").await }
+/// });
+/// // Dropped while authenticating, with the spawned call waiting for it
+/// assert!(tokio::time::timeout(Duration::from_millis(10), first).await.is_err());
+///
+/// assert!(waiting.await.unwrap().is_ok());
+/// assert!(manager.get_client("key", "secret").await.is_ok());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientManager {
+    inner: Arc<Inner>,
+}
+
+/// A client along with the token it is authorized with
+#[derive(Debug, Clone)]
+struct Authorized {
+    client: Client,
+    token: AccessToken,
+}
+
+/// The outcome of a single authentication, shared by every caller waiting on it
+type Flight = OnceCell<Result<Authorized, Error>>;
+
+#[derive(Debug)]
+struct InFlight {
+    flight: Arc<Flight>,
+    /// Started by [`ClientManager::force_refresh`], joined by concurrent forced refreshes
+    forced: bool,
+}
+
+/// All maps are guarded by blocking locks that are only ever held for a quick
+/// lookup or insert and never across an `.await`. Cache hits only take the read lock
+/// of `clients`, so that they don't contend with each other.
+struct Inner {
+    config: ManagerConfig,
+    authenticator: Arc<dyn Authenticator>,
+    clock: Arc<dyn Clock>,
+    metrics: Arc<dyn Metrics>,
+    store: Option<Arc<dyn TokenStore>>,
+    /// Keys are derived by [`by_api_key`] if `None`, which allows looking them up without allocating
+    key_deriver: Option<Arc<dyn KeyDeriver>>,
+    breaker: Option<CircuitBreaker>,
+    limiter: Option<RateLimiter>,
+    clients: RwLock<Cache>,
+    secret_hasher: SecretHasher,
+    jitter: JitterRng,
+    /// Authentications currently in progress, concurrent callers for the same key share one
+    ///
+    /// Acts as a per-key lock: the map is locked only to find or insert the flight of a key,
+    /// callers then wait on the flight itself, so authenticating one key never delays another.
+    /// A flight is removed once it's finished, the map only holds keys being authenticated.
+    in_flight: Mutex<HashMap<CacheKey, InFlight>>,
+    rejected: Mutex<HashMap<CacheKey, Rejected>>,
+    /// Number of clients evicted to stay within `max_capacity`
+    evictions: AtomicU64,
+    /// Incremented by every authentication as it starts, see [`ExpiringClient::generation`]
+    generation: AtomicU64,
+    events: Events,
+    /// Periodically evicts expired clients, holds only a weak reference to the manager
+    evictor: Mutex<Option<JoinHandle<()>>>,
+    /// Credentials of the clients refreshed in the background
+    warm: Mutex<HashMap<CacheKey, Warm>>,
+    /// Refreshes the clients in `warm`, spawned by the first call to `keep_warm`
+    warmer: Mutex<Option<JoinHandle<()>>>,
+    /// Providers registered by name, along with the key of the client last authenticated with
+    /// their credentials
+    providers: Mutex<HashMap<String, Provided>>,
+    /// The latest rotation of every key passed to [`ClientManager::rotate_credentials`]
+    rotations: Mutex<HashMap<CacheKey, Rotation>>,
+    /// Set by [`ClientManager::shutdown`], after which no background task is spawned
+    shut_down: AtomicBool,
+    /// Becomes `true` on [`ClientManager::shutdown`], cancelling the authentications in progress
+    stopped: watch::Sender<bool>,
+    /// The client of every token in [`ClientMode::Shared`], built on first use
+    shared: once_cell::sync::OnceCell<Client>,
+}
+
+#[cfg(feature = "blocking")]
+pub(crate) type BlockingSlot = Arc<once_cell::sync::OnceCell<reqwest::blocking::Client>>;
+
+/// A secret set by [`ClientManager::rotate_credentials`], used in place of the secrets it retired
+#[derive(Debug)]
+struct Rotation {
+    /// Authentications with other secrets that started before the rotation aren't cached
+    generation: u64,
+    secret: ApiSecret,
+    secret_hash: SecretHash,
+    retired: Vec<SecretHash>,
+}
+
+/// A recently rejected authentication, returned again without asking the backend
+#[derive(Debug)]
+struct Rejected {
+    error: Error,
+    secret_hash: SecretHash,
+    until: i64,
+}
+
+struct Provided {
+    provider: Arc<dyn CredentialsProvider>,
+    key: Option<CacheKey>,
+}
+
+impl fmt::Debug for Provided {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Provided")
+            .field("key", &self.key)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+struct Warm {
+    api_secret: ApiSecret,
+    last_requested: i64,
+    failures: u32,
+    retry_at: i64,
+}
+
+impl Inner {
+    fn evict_expired(&self) -> usize {
+        // Clients that may still be served after a failed refresh are kept as well
+        let grace = self
+            .config
+            .eviction_grace
+            .max(self.config.serve_stale_for.unwrap_or(0));
+        let deadline = offset(self.clock.instant(), -grace);
+
+        let evicted = self
+            .clients
+            .write()
+            .unwrap()
+            .retain(|_, client| !client.is_expired(deadline));
+        for _ in &evicted {
+            self.metrics.on_eviction();
+        }
+        if !evicted.is_empty() {
+            event!(DEBUG, evicted = evicted.len(), "Evicted expired clients");
+        }
+
+        let count = evicted.len();
+        self.events.evicted(evicted);
+
+        count
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        for task in [&mut self.evictor, &mut self.warmer] {
+            if let Some(task) = task.get_mut().unwrap() {
+                task.abort();
+            }
+        }
+    }
+}
+
+fn spawn_evictor(inner: Weak<Inner>, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        // A sweep that was delayed by a busy runtime shouldn't be followed by a burst of catch-up sweeps
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            let Some(manager) = inner.upgrade() else {
+                break;
+            };
+            manager.evict_expired();
+        }
+    })
+}
+
+fn spawn_warmer(inner: Weak<Inner>, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            let Some(inner) = inner.upgrade() else {
+                break;
+            };
+            ClientManager { inner }.refresh_warm().await;
+        }
+    })
+}
+
+impl fmt::Debug for Inner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Inner")
+            .field("config", &self.config)
+            .field("clients", &self.clients)
+            .field("in_flight", &self.in_flight)
+            .field("evictions", &self.evictions)
+            .field("warm", &self.warm)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for ClientManager {
+    /// A manager with the default config and its own, empty cache
+    fn default() -> Self {
+        Self::new(ManagerConfig::default())
+    }
+}
+
+impl ClientManager {
+    pub fn builder() -> ClientManagerBuilder {
+        ClientManagerBuilder::new()
+    }
+
+    pub fn new(config: ManagerConfig) -> Self {
+        Self::with_authenticator(config, Arc::new(StaticAuthenticator))
+    }
+
+    /// Like [`new`](Self::new), but fails on a config that doesn't pass
+    /// [`ManagerConfig::validate`], e.g. one read from a file
+    ///
+    /// Spawns the eviction task if the config has an `eviction_interval`, which then has to
+    /// happen within a Tokio runtime.
+    pub fn from_config(config: ManagerConfig) -> Result<Self, Error> {
+        config.validate()?;
+
+        Ok(Self::new(config))
+    }
+
+    /// Creates a manager that obtains tokens from `authenticator`
+    pub fn with_authenticator(
+        config: ManagerConfig,
+        authenticator: Arc<dyn Authenticator>,
+    ) -> Self {
+        Self::with_parts(
+            config,
+            authenticator,
+            Arc::new(SystemClock),
+            Arc::new(NoopMetrics),
+        )
+    }
+
+    /// Creates a manager that takes the current time from `clock` instead of the system clock
+    pub fn with_clock(config: ManagerConfig, clock: impl Clock + 'static) -> Self {
+        Self::with_parts(
+            config,
+            Arc::new(StaticAuthenticator),
+            Arc::new(clock),
+            Arc::new(NoopMetrics),
+        )
+    }
+
+    pub fn with_parts(
+        config: ManagerConfig,
+        authenticator: Arc<dyn Authenticator>,
+        clock: Arc<dyn Clock>,
+        metrics: Arc<dyn Metrics>,
+    ) -> Self {
+        Self::from_parts(config, authenticator, clock, metrics, None, None)
+    }
+
+    /// Creates a manager that restores tokens from `store` instead of authenticating
+    /// whenever possible, and saves every new token in it
+    ///
+    /// The store comes in addition to the in-memory cache rather than replacing it: the cache
+    /// holds the client built around every token, which can't be stored, and serves hits without
+    /// an `await`. That's why there is no store by default, a
+    /// [`MemoryTokenStore`](crate::MemoryTokenStore) would only duplicate the cache unless it's
+    /// shared by several managers.
+    pub fn with_token_store(
+        config: ManagerConfig,
+        authenticator: Arc<dyn Authenticator>,
+        store: Arc<dyn TokenStore>,
+    ) -> Self {
+        Self::from_parts(
+            config,
+            authenticator,
+            Arc::new(SystemClock),
+            Arc::new(NoopMetrics),
+            Some(store),
+            None,
+        )
+    }
+
+    /// Creates a manager that caches clients under the keys derived by `key_deriver`, see
+    /// [`get_client_with_context`](Self::get_client_with_context)
+    pub fn with_key_deriver(
+        config: ManagerConfig,
+        authenticator: Arc<dyn Authenticator>,
+        key_deriver: impl KeyDeriver + 'static,
+    ) -> Self {
+        Self::from_parts(
+            config,
+            authenticator,
+            Arc::new(SystemClock),
+            Arc::new(NoopMetrics),
+            None,
+            Some(Arc::new(key_deriver)),
+        )
+    }
+
+    pub(crate) fn from_parts(
+        config: ManagerConfig,
+        authenticator: Arc<dyn Authenticator>,
+        clock: Arc<dyn Clock>,
+        metrics: Arc<dyn Metrics>,
+        store: Option<Arc<dyn TokenStore>>,
+        key_deriver: Option<Arc<dyn KeyDeriver>>,
+    ) -> Self {
+        Self {
+            inner: Arc::new_cyclic(|inner| Inner {
+                clients: RwLock::new(Cache::new(config.max_capacity)),
+                breaker: config.circuit_breaker.clone().map(CircuitBreaker::new),
+                limiter: config.rate_limit.clone().map(RateLimiter::new),
+                jitter: JitterRng::new(config.jitter_seed),
+                evictor: Mutex::new(
+                    config
+                        .eviction_interval
+                        .map(|interval| spawn_evictor(inner.clone(), interval)),
+                ),
+                config,
+                authenticator,
+                clock,
+                metrics,
+                store,
+                key_deriver,
+                in_flight: Mutex::new(HashMap::new()),
+                rejected: Mutex::new(HashMap::new()),
+                evictions: AtomicU64::new(0),
+                generation: AtomicU64::new(0),
+                events: Events::new(),
+                secret_hasher: SecretHasher::new(),
+                warm: Mutex::new(HashMap::new()),
+                warmer: Mutex::new(None),
+                providers: Mutex::new(HashMap::new()),
+                rotations: Mutex::new(HashMap::new()),
+                shut_down: AtomicBool::new(false),
+                stopped: watch::channel(false).0,
+                shared: once_cell::sync::OnceCell::new(),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &ManagerConfig {
+        &self.inner.config
+    }
+
+    /// Returns a cached client for `api_key` or authenticates and caches a new one
+    /// if there is none or it has expired
+    ///
+    /// The cache lock is not held while authenticating, concurrent callers for the
+    /// same `api_key` wait for a single authentication while other keys proceed independently.
+    /// If that authentication fails, every waiter receives the error and nothing is cached.
+    ///
+    /// The returned client is a handle sharing the connection pool of the cached one. It stays
+    /// usable for as long as it's held, also after the cached client was refreshed, invalidated
+    /// or evicted, which only drop the manager's handle. Requests in progress are never cut off,
+    /// but the token it's authorized with may expire or be revoked in the meantime.
+    pub async fn get_client(
+        &self,
+        api_key: impl Into<ApiKey>,
+        api_secret: impl Into<ApiSecret>,
+    ) -> Result<Client, Error> {
+        Ok(self
+            .get(self.key(api_key.into().into_inner()), api_secret.into())
+            .await?
+            .client)
+    }
+
+    /// Like [`get_client`](Self::get_client), but borrows the credentials and doesn't allocate when
+    /// serving a cached client
+    ///
+    /// The key and secret are only copied to authenticate. Managers with a custom [`KeyDeriver`]
+    /// always derive an owned key, for them this is the same as `get_client`.
+    pub async fn get_client_ref(&self, api_key: &str, api_secret: &str) -> Result<Client, Error> {
+        let (client, _) = self.get_client_detailed(api_key, api_secret).await?;
+
+        Ok(client)
+    }
+
+    /// Like [`get_client_ref`](Self::get_client_ref), but also tells whether the client was cached
+    /// or authenticated, e.g. to log it for a single handler
+    pub async fn get_client_detailed(
+        &self,
+        api_key: &str,
+        api_secret: &str,
+    ) -> Result<(Client, CacheOutcome), Error> {
+        if self.inner.key_deriver.is_none() {
+            let key = BorrowedKey {
+                auth: &self.inner.config.auth,
+                api_key,
+                derived: api_key,
+            };
+            let key: &dyn KeyView = &key;
+            let now = self.inner.clock.instant();
+
+            if let Some(warm) = self.inner.warm.lock().unwrap().get_mut(key) {
+                warm.last_requested = self.inner.clock.now();
+            }
+
+            let secret_hash = self.inner.secret_hasher.hash_str(api_secret);
+            let cached = {
+                let clients = self.inner.clients.read().unwrap();
+                clients
+                    .get(key, now)
+                    .filter(|client| {
+                        client.secret_hash == secret_hash
+                            && client.is_fresh(now, self.inner.config.refresh_skew)
+                    })
+                    .map(|client| client.client.clone())
+            };
+            if let Some(client) = cached {
+                self.inner.metrics.on_cache_hit();
+                event!(TRACE, "Cache hit");
+                return Ok((client, CacheOutcome::Hit));
+            }
+        }
+
+        let (authorized, outcome) = self
+            .get_detailed(self.key(api_key.to_string()), api_secret.into())
+            .await?;
+
+        Ok((authorized.client, outcome))
+    }
+
+    /// Like [`get_client`](Self::get_client), but authenticates against `auth` instead of the
+    /// configured app client and pool
+    ///
+    /// Clients are cached per app client id, pool id and api key, so the same api key used
+    /// with several pools gets a separate client for each of them.
+    pub async fn get_client_for(
+        &self,
+        auth: &AuthConfig,
+        api_key: impl Into<ApiKey>,
+        api_secret: impl Into<ApiSecret>,
+    ) -> Result<Client, Error> {
+        let key = self.cache_key(auth, ClientContext::new(api_key));
+
+        Ok(self.get(key, api_secret.into()).await?.client)
+    }
+
+    /// Like [`get_client`](Self::get_client), but caches the client under the key derived from
+    /// `context`, and adds the headers of `context` to it
+    ///
+    /// Without a [`KeyDeriver`] passed to [`with_key_deriver`](Self::with_key_deriver) all
+    /// contexts for the same api key share one client, created for whichever context came first.
+    /// Methods taking only an api key refer to the client of a context without tenant or headers.
+    pub async fn get_client_with_context(
+        &self,
+        context: ClientContext,
+        api_secret: impl Into<ApiSecret>,
+    ) -> Result<Client, Error> {
+        let key = self.cache_key(&self.inner.config.auth, context);
+
+        Ok(self.get(key, api_secret.into()).await?.client)
+    }
+
+    /// Like [`get_client`](Self::get_client), but for a token granted at least `scopes`
+    ///
+    /// Clients are cached per set of requested scopes, regardless of their order. A cached client
+    /// whose token was granted a superset of `scopes` is served instead of authenticating, which
+    /// takes a scan of the cache. Fails with [`Error::ScopeNotGranted`] if the backend reports
+    /// granting fewer scopes, see [`Authenticator::authenticate_scoped`]. Scoped tokens aren't
+    /// shared through a [`TokenStore`]. Without scopes this is the same as `get_client`.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), existing_code_challenge::Error> {
+    /// use existing_code_challenge::ClientManager;
+    ///
+    /// let manager = ClientManager::default();
+    /// manager
+    ///     .get_client_with_scope("key", "secret", ["write:orders", "read:orders"])
+    ///     .await?;
+    /// // The same set of scopes in another order, and a subset of them, share the client
+    /// manager
+    ///     .get_client_with_scope("key", "secret", ["read:orders", "write:orders"])
+    ///     .await?;
+    /// manager
+    ///     .get_client_with_scope("key", "secret", ["read:orders"])
+    ///     .await?;
+    /// assert_eq!(manager.len().await, 1);
+    ///
+    /// manager
+    ///     .get_client_with_scope("key", "secret", ["read:invoices"])
+    ///     .await?;
+    /// assert_eq!(manager.len().await, 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn get_client_with_scope(
+        &self,
+        api_key: impl Into<ApiKey>,
+        api_secret: impl Into<ApiSecret>,
+        scopes: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<Client, Error> {
+        let key = self.key(api_key.into().into_inner());
+        let api_secret = api_secret.into();
+        let scopes = normalize_scopes(scopes.into_iter().map(Into::into));
+        if scopes.is_empty() {
+            return Ok(self.get(key, api_secret).await?.client);
+        }
+
+        let key = key.with_scopes(scopes);
+        let secret_hash = self.inner.secret_hasher.hash(&api_secret);
+        if let Some(authorized) = self.covering(&key, &secret_hash) {
+            self.inner.metrics.on_cache_hit();
+            event!(TRACE, "Cache hit of a token covering the scopes");
+            return Ok(authorized.client);
+        }
+
+        Ok(self.get(key, api_secret).await?.client)
+    }
+
+    /// Registers `provider` under `name`, replacing any provider registered before, see
+    /// [`get_client_by_name`](Self::get_client_by_name)
+    pub fn register_credentials(
+        &self,
+        name: impl Into<String>,
+        provider: Arc<dyn CredentialsProvider>,
+    ) {
+        self.inner.providers.lock().unwrap().insert(
+            name.into(),
+            Provided {
+                provider,
+                key: None,
+            },
+        );
+    }
+
+    /// Like [`get_client`](Self::get_client), but with the credentials of the provider registered
+    /// under `name`
+    ///
+    /// The provider is only asked for the credentials if there's no fresh client for the api key
+    /// it returned last time, then a changed secret is authenticated as with `get_client`.
+    pub async fn get_client_by_name(&self, name: &str) -> Result<Client, Error> {
+        let (provider, key) = {
+            let providers = self.inner.providers.lock().unwrap();
+            let provided = providers
+                .get(name)
+                .ok_or_else(|| Error::UnknownCredentials(name.to_string()))?;
+
+            (provided.provider.clone(), provided.key.clone())
+        };
+
+        if let Some(key) = &key {
+            let now = self.inner.clock.instant();
+            let cached = {
+                let clients = self.inner.clients.read().unwrap();
+                clients
+                    .get(key, now)
+                    .filter(|client| client.is_fresh(now, self.inner.config.refresh_skew))
+                    .map(|client| client.client.clone())
+            };
+            if let Some(client) = cached {
+                self.inner.metrics.on_cache_hit();
+                return Ok(client);
+            }
+        }
+
+        let (api_key, api_secret) = provider
+            .credentials()
+            .await
+            .map_err(Error::credentials_unavailable)?;
+        let key = self.key(api_key.into_inner());
+
+        if let Some(provided) = self.inner.providers.lock().unwrap().get_mut(name) {
+            if Arc::ptr_eq(&provided.provider, &provider) {
+                provided.key = Some(key.clone());
+            }
+        }
+
+        Ok(self.get(key, api_secret).await?.client)
+    }
+
+    /// Like [`get_client`](Self::get_client), but also returns the unix timestamp at which the
+    /// token of the client expires
+    ///
+    /// Useful to check that the client stays valid for the duration of a long operation.
+    pub async fn get_client_with_expiry(
+        &self,
+        api_key: impl Into<ApiKey>,
+        api_secret: impl Into<ApiSecret>,
+    ) -> Result<(Client, i64), Error> {
+        let authorized = self
+            .get(self.key(api_key.into().into_inner()), api_secret.into())
+            .await?;
+        let expires_at = authorized.token.expires_at();
+
+        Ok((authorized.client, expires_at))
+    }
+
+    /// Returns the access token for `api_key`, sharing the cache with [`get_client`](Self::get_client)
+    ///
+    /// The returned token is always the one the client returned by `get_client` is authorized with.
+    pub async fn get_token(
+        &self,
+        api_key: impl Into<ApiKey>,
+        api_secret: impl Into<ApiSecret>,
+    ) -> Result<AccessToken, Error> {
+        Ok(self
+            .get(self.key(api_key.into().into_inner()), api_secret.into())
+            .await?
+            .token)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "get_client",
+            skip_all,
+            fields(key = %crate::credentials::fingerprint(key.api_key())),
+        )
+    )]
+    async fn get(&self, key: CacheKey, api_secret: ApiSecret) -> Result<Authorized, Error> {
+        let (authorized, _) = self.get_detailed(key, api_secret).await?;
+
+        Ok(authorized)
+    }
+
+    async fn get_detailed(
+        &self,
+        key: CacheKey,
+        api_secret: ApiSecret,
+    ) -> Result<(Authorized, CacheOutcome), Error> {
+        self.ensure_running()?;
+        if let Some(warm) = self.inner.warm.lock().unwrap().get_mut(&key) {
+            warm.last_requested = self.inner.clock.now();
+        }
+
+        let (api_secret, secret_hash) = self.rotated(&key, api_secret);
+        if let Some(authorized) = self.cached(&key, &secret_hash) {
+            self.inner.metrics.on_cache_hit();
+            event!(TRACE, "Cache hit");
+            return Ok((authorized, CacheOutcome::Hit));
+        }
+        self.inner.metrics.on_cache_miss();
+
+        #[cfg(feature = "tracing")]
+        let stale = self.inner.clients.read().unwrap().peek(&key).is_some();
+        event!(DEBUG, stale, "Cache miss");
+
+        if let Some(err) = self.rejected(&key, &secret_hash) {
+            event!(DEBUG, "Credentials were rejected recently");
+            return Err(err);
+        }
+
+        let (flight, forced) = self.flight(&key);
+        let hit = AtomicBool::new(false);
+
+        let authorized = self
+            .join(&key, flight, || async {
+                // Someone else might have refreshed the client while we were waiting
+                if let Some(authorized) = self.cached(&key, &secret_hash) {
+                    hit.store(true, Ordering::Relaxed);
+                    return Ok(authorized);
+                }
+
+                self.authenticate(&key, &api_secret, false).await
+            })
+            .await?;
+        let outcome = if hit.load(Ordering::Relaxed) {
+            CacheOutcome::Hit
+        } else if forced {
+            CacheOutcome::Forced
+        } else {
+            CacheOutcome::Refreshed
+        };
+
+        Ok((authorized, outcome))
+    }
+
+    /// The token of `api_key`, along with the slot of the blocking client authorized with it if
+    /// the token is cached
+    #[cfg(feature = "blocking")]
+    pub(crate) async fn blocking_slot(
+        &self,
+        api_key: ApiKey,
+        api_secret: ApiSecret,
+    ) -> Result<(AccessToken, Option<BlockingSlot>), Error> {
+        let key = self.key(api_key.into_inner());
+        let authorized = self.get(key.clone(), api_secret).await?;
+        let slot = self
+            .inner
+            .clients
+            .read()
+            .unwrap()
+            .peek(&key)
+            .filter(|client| client.token == authorized.token)
+            .map(|client| client.blocking.clone());
+
+        Ok((authorized.token, slot))
+    }
+
+    /// Authenticates again regardless of any cached client and replaces it
+    ///
+    /// The cached client is dropped right away, so calls to [`get_client`](Self::get_client) made
+    /// while the refresh is in progress wait for it instead of being served the replaced client.
+    /// An older authentication that finishes afterwards doesn't overwrite the result.
+    ///
+    /// Concurrent forced refreshes for the same `api_key`, e.g. by several tasks that got a 401
+    /// at once, share a single authentication. Recently rejected credentials are tried again
+    /// as well.
+    pub async fn force_refresh(
+        &self,
+        api_key: impl Into<ApiKey>,
+        api_secret: impl Into<ApiSecret>,
+    ) -> Result<Client, Error> {
+        let key = self.key(api_key.into().into_inner());
+
+        Ok(self.force(key, api_secret.into()).await?.client)
+    }
+
+    async fn force(&self, key: CacheKey, api_secret: ApiSecret) -> Result<Authorized, Error> {
+        self.ensure_running()?;
+        let (api_secret, _) = self.rotated(&key, api_secret);
+
+        let flight = {
+            let mut in_flight = self.inner.in_flight.lock().unwrap();
+            match in_flight.get(&key) {
+                Some(current) if current.forced && !current.flight.initialized() => {
+                    current.flight.clone()
+                }
+                _ => {
+                    // Holding the flight lock while dropping the client makes callers that miss
+                    // the cache from now on join this flight
+                    self.inner.clients.write().unwrap().remove(&key);
+
+                    let flight = Arc::new(Flight::new());
+                    in_flight.insert(
+                        key.clone(),
+                        InFlight {
+                            flight: flight.clone(),
+                            forced: true,
+                        },
+                    );
+                    flight
+                }
+            }
+        };
+
+        self.join(&key, flight, || self.authenticate(&key, &api_secret, true))
+            .await
+    }
+
+    /// Replaces the secret of `api_key`, authenticating with `new_secret` right away
+    ///
+    /// The client authenticated with the previous secret is served until the new one is cached,
+    /// and keeps working for whoever still holds it. If authenticating fails it stays cached and
+    /// [`Error::RotationFailed`] is returned.
+    ///
+    /// Once the rotation succeeded, the new secret is stored:
+    /// - Callers still passing the secret of the client cached before, or of an earlier
+    ///   rotation, are served the client of the new secret, which is also used to refresh it.
+    /// - Authentications with another secret that were in progress when the rotation started
+    ///   are returned to their callers but not cached.
+    /// - A client kept warm is refreshed with the new secret.
+    /// - The new secret is [stored](CredentialsProvider::store) in every registered provider
+    ///   whose credentials were last used for `api_key`. A provider failing to store it is logged
+    ///   with the `tracing` feature.
+    pub async fn rotate_credentials(
+        &self,
+        api_key: impl Into<ApiKey>,
+        new_secret: impl Into<ApiSecret>,
+    ) -> Result<Client, Error> {
+        self.ensure_running()?;
+        let key = self.key(api_key.into().into_inner());
+        let new_secret = new_secret.into();
+        let secret_hash = self.inner.secret_hasher.hash(&new_secret);
+        let generation = self.inner.generation.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let previous = {
+            let mut rotations = self.inner.rotations.lock().unwrap();
+            let mut retired = rotations
+                .get(&key)
+                .map(|rotation| {
+                    let mut retired = rotation.retired.clone();
+                    retired.push(rotation.secret_hash);
+                    retired
+                })
+                .unwrap_or_default();
+            retired.extend(
+                self.inner
+                    .clients
+                    .read()
+                    .unwrap()
+                    .peek(&key)
+                    .map(|client| client.secret_hash),
+            );
+            retired.retain(|retired| *retired != secret_hash);
+
+            rotations.insert(
+                key.clone(),
+                Rotation {
+                    generation,
+                    secret: new_secret.clone(),
+                    secret_hash,
+                    retired,
+                },
+            )
+        };
+
+        // Unlike a forced refresh, this never joins an authentication with another secret
+        let flight = Arc::new(Flight::new());
+        self.inner.in_flight.lock().unwrap().insert(
+            key.clone(),
+            InFlight {
+                flight: flight.clone(),
+                forced: true,
+            },
+        );
+
+        let res = self
+            .join(&key, flight, || self.authenticate(&key, &new_secret, true))
+            .await;
+        let authorized = match res {
+            Ok(authorized) => authorized,
+            Err(err) => {
+                // The previous secret is still the one in use, unless another rotation followed
+                let mut rotations = self.inner.rotations.lock().unwrap();
+                if rotations
+                    .get(&key)
+                    .is_some_and(|rotation| rotation.generation == generation)
+                {
+                    match previous {
+                        Some(previous) => rotations.insert(key.clone(), previous),
+                        None => rotations.remove(&key),
+                    };
+                }
+
+                return Err(Error::RotationFailed(Arc::new(err)));
+            }
+        };
+
+        if let Some(warm) = self.inner.warm.lock().unwrap().get_mut(&key) {
+            warm.api_secret = new_secret.clone();
+            warm.failures = 0;
+        }
+
+        let providers: Vec<_> = self
+            .inner
+            .providers
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|provided| provided.key.as_ref() == Some(&key))
+            .map(|provided| provided.provider.clone())
+            .collect();
+        for provider in providers {
+            if let Err(_err) = provider.store(key.api_key(), &new_secret).await {
+                event!(WARN, error = %_err, "Failed to store rotated credentials");
+            }
+        }
+        event!(DEBUG, "Rotated credentials");
+
+        Ok(authorized.client)
+    }
+
+    /// The secret to use for `key` in place of `api_secret`, if it was retired by a rotation,
+    /// along with its hash
+    fn rotated(&self, key: &CacheKey, api_secret: ApiSecret) -> (ApiSecret, SecretHash) {
+        let secret_hash = self.inner.secret_hasher.hash(&api_secret);
+        let rotations = self.inner.rotations.lock().unwrap();
+        match rotations.get(key) {
+            Some(rotation) if rotation.retired.contains(&secret_hash) => {
+                (rotation.secret.clone(), rotation.secret_hash)
+            }
+            _ => (api_secret, secret_hash),
+        }
+    }
+
+    /// Whether an authentication with `secret_hash` that started as `generation` was superseded
+    /// by a rotation of `key` to another secret, which then retires its secret as well
+    fn superseded(&self, key: &CacheKey, generation: u64, secret_hash: SecretHash) -> bool {
+        let mut rotations = self.inner.rotations.lock().unwrap();
+        let Some(rotation) = rotations.get_mut(key) else {
+            return false;
+        };
+        if generation > rotation.generation || rotation.secret_hash == secret_hash {
+            return false;
+        }
+
+        if !rotation.retired.contains(&secret_hash) {
+            rotation.retired.push(secret_hash);
+        }
+        true
+    }
+
+    /// Sends the request built by `build` with the client for `api_key`, and sends it again with a
+    /// refreshed client if the token is rejected, as configured by the [`ReplayPolicy`](crate::ReplayPolicy)
+    ///
+    /// `build` is called again for every replay, so it may send requests of any method or body.
+    /// The response is returned as is once it's not a rejection or no replays are left.
+    pub async fn request_with_retry(
+        &self,
+        api_key: impl Into<ApiKey>,
+        api_secret: impl Into<ApiSecret>,
+        build: impl Fn(&Client) -> RequestBuilder,
+    ) -> Result<Response, Error> {
+        let api_key = api_key.into();
+        let api_secret = api_secret.into();
+        let policy = &self.inner.config.replay;
+
+        let mut authorized = self.authorize(&api_key, &api_secret, false).await?;
+        let mut replays = 0;
+        loop {
+            let (client, headers) = &authorized;
+            let response = build(client)
+                .headers(headers.clone())
+                .send()
+                .await
+                .map_err(Error::request)?;
+            if replays >= policy.max_replays || !policy.statuses.contains(&response.status()) {
+                return Ok(response);
+            }
+
+            event!(DEBUG, status = %response.status(), "Replaying a rejected request");
+            authorized = self.authorize(&api_key, &api_secret, true).await?;
+            replays += 1;
+        }
+    }
+
+    /// Builds a `method` request to `url` authorized with the token for `api_key`, authenticating
+    /// as [`get_client`](Self::get_client) does
+    ///
+    /// In [`ClientMode::Shared`] the request is sent by the manager's single client, carrying the
+    /// authentication headers of the current token. Otherwise it's built by the client of the token.
+    pub async fn authorized_request(
+        &self,
+        method: Method,
+        url: impl IntoUrl,
+        api_key: impl Into<ApiKey>,
+        api_secret: impl Into<ApiSecret>,
+    ) -> Result<RequestBuilder, Error> {
+        let (client, headers) = self
+            .authorize(&api_key.into(), &api_secret.into(), false)
+            .await?;
+
+        Ok(client.request(method, url).headers(headers))
+    }
+
+    /// The client for `api_key` and the headers every request has to carry on top of the
+    /// client's own, which are only the authentication headers in [`ClientMode::Shared`]
+    ///
+    /// Forcing refreshes the token as [`force_refresh`](Self::force_refresh) does.
+    pub(crate) async fn authorize(
+        &self,
+        api_key: &ApiKey,
+        api_secret: &ApiSecret,
+        force: bool,
+    ) -> Result<(Client, HeaderMap), Error> {
+        let (key, authorized) = self.authorized(api_key, api_secret, force).await?;
+        let headers = match self.inner.config.client_mode {
+            ClientMode::PerToken => HeaderMap::new(),
+            ClientMode::Shared => self.headers(&key, authorized.token.as_str())?,
+        };
+
+        Ok((authorized.client, headers))
+    }
+
+    /// The headers a request authorized with the token for `api_key` carries, regardless of the
+    /// [`ClientMode`], for requests sent by other clients
+    #[cfg(any(feature = "middleware", feature = "tower"))]
+    pub(crate) async fn authorization(
+        &self,
+        api_key: &ApiKey,
+        api_secret: &ApiSecret,
+        force: bool,
+    ) -> Result<HeaderMap, Error> {
+        let (key, authorized) = self.authorized(api_key, api_secret, force).await?;
+
+        self.headers(&key, authorized.token.as_str())
+    }
+
+    async fn authorized(
+        &self,
+        api_key: &ApiKey,
+        api_secret: &ApiSecret,
+        force: bool,
+    ) -> Result<(CacheKey, Authorized), Error> {
+        let key = self.key(api_key.as_str().to_string());
+        let authorized = if force {
+            self.force(key.clone(), api_secret.clone()).await?
+        } else {
+            self.get(key.clone(), api_secret.clone()).await?
+        };
+
+        Ok((key, authorized))
+    }
+
+    /// Authenticates all `credentials` concurrently, at most
+    /// [`refresh_concurrency`](ManagerConfig::refresh_concurrency) at a time, and caches their
+    /// clients, e.g. during startup
+    ///
+    /// Returns the outcome for every api key, in the order of `credentials`. A failure doesn't
+    /// affect the other clients, and credentials already cached or being authenticated by
+    /// another caller aren't authenticated again. Authentications cancelled because the runtime
+    /// shuts down fail with [`Error::ShutDown`].
+    pub async fn warm_up(
+        &self,
+        credentials: impl IntoIterator<Item = (ApiKey, ApiSecret)>,
+    ) -> Vec<(ApiKey, Result<(), Error>)> {
+        let permits = Arc::new(Semaphore::new(self.inner.config.refresh_concurrency.max(1)));
+        let mut tasks = JoinSet::new();
+        let mut results = Vec::new();
+        for (index, (api_key, api_secret)) in credentials.into_iter().enumerate() {
+            let manager = self.clone();
+            let permits = permits.clone();
+            let key = self.key(api_key.as_str().to_string());
+
+            tasks.spawn(async move {
+                let _permit = permits.acquire().await;
+
+                (index, manager.get(key, api_secret).await.map(drop))
+            });
+            results.push((api_key, Err(Error::ShutDown)));
+        }
+
+        while let Some(res) = tasks.join_next().await {
+            match res {
+                Ok((index, res)) => results[index].1 = res,
+                Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+                // Only cancelled by the runtime shutting down, the result stays `ShutDown`
+                Err(_) => {}
+            }
+        }
+
+        results
+    }
+
+    /// Re-authenticates every stale client whose secret is known, at most
+    /// [`refresh_concurrency`](ManagerConfig::refresh_concurrency) at a time
+    ///
+    /// Secrets are known for clients opted in with [`keep_warm`](Self::keep_warm), or for all clients if
+    /// [`retain_credentials`](ManagerConfig::retain_credentials) is set. Useful to get ahead
+    /// of the traffic e.g. after the process was suspended for a while.
+    /// Returns the outcome of every refresh by api key.
+    pub async fn refresh_all_expired(&self) -> HashMap<String, Result<(), Error>> {
+        let now = self.inner.clock.instant();
+
+        let warm: Vec<_> = self
+            .inner
+            .warm
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, warm)| (key.clone(), warm.api_secret.clone()))
+            .collect();
+        let mut stale: HashMap<_, _> = warm
+            .into_iter()
+            .filter(|(key, api_secret)| {
+                let secret_hash = self.inner.secret_hasher.hash(api_secret);
+                self.fresh_at(key, &secret_hash, now).is_none()
+            })
+            .collect();
+
+        for (key, client) in self.inner.clients.read().unwrap().iter() {
+            if let Some(api_secret) = &client.api_secret {
+                if !client.is_fresh(now, self.inner.config.refresh_skew) {
+                    stale
+                        .entry(key.clone())
+                        .or_insert_with(|| api_secret.clone());
+                }
+            }
+        }
+
+        let permits = Arc::new(Semaphore::new(self.inner.config.refresh_concurrency.max(1)));
+        let mut refreshes = JoinSet::new();
+        for (key, api_secret) in stale {
+            let manager = self.clone();
+            let permits = permits.clone();
+
+            refreshes.spawn(async move {
+                let _permit = permits.acquire().await;
+                let res = manager.refresh_stale(&key, &api_secret, now).await;
+
+                (key.api_key().to_string(), res.map(drop))
+            });
+        }
+
+        let mut results = HashMap::new();
+        while let Some(res) = refreshes.join_next().await {
+            match res {
+                Ok((api_key, res)) => {
+                    results.insert(api_key, res);
+                }
+                Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+                Err(_) => {}
+            }
+        }
+
+        results
+    }
+
+    /// Returns the cached client for `api_key` if it's still fresh, never authenticating
+    ///
+    /// Unlike [`get_client`](Self::get_client) this doesn't check which secret the client was
+    /// authenticated with, and returns `None` for a client that is about to go stale.
+    pub fn get_cached_client(&self, api_key: &str) -> Option<Client> {
+        let key = self.key(api_key.to_string());
+        let now = self.inner.clock.instant();
+
+        let clients = self.inner.clients.read().unwrap();
+        let client = clients.get(&key, now)?;
+
+        client
+            .is_fresh(now, self.inner.config.refresh_skew)
+            .then(|| client.client.clone())
+    }
+
+    /// Delegates to [`get_cached_client`](Self::get_cached_client), for fast paths that fall back
+    /// to something else than authenticating when no fresh client is cached
+    ///
+    /// Never authenticates nor waits for an authentication in flight.
+    pub async fn peek(&self, api_key: &str) -> Option<Client> {
+        self.get_cached_client(api_key)
+    }
+
+    /// Unix timestamp at which the token cached for `api_key` was obtained, e.g. for audit logs,
+    /// even if it expired already
+    ///
+    /// For a token restored from the [`TokenStore`] that's when the other process obtained it.
+    pub fn issued_at(&self, api_key: &str) -> Option<i64> {
+        let key = self.key(api_key.to_string());
+
+        Some(self.inner.clients.read().unwrap().peek(&key)?.issued_at)
+    }
+
+    /// How long the token cached for `api_key` is served before it's refreshed, e.g. to decide
+    /// whether a long job can still be started with it, `None` if there is none or it's stale
+    ///
+    /// Accounts for the [`refresh_skew`](ManagerConfig::refresh_skew) and jitter, so the token
+    /// itself is valid for longer.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), existing_code_challenge::Error> {
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    ///
+    /// use existing_code_challenge::{ClientManager, MockClock};
+    ///
+    /// // The default authenticator's tokens last an hour
+    /// let clock = MockClock::new(1_000);
+    /// let manager = ClientManager::builder()
+    ///     .clock(Arc::new(clock.clone()))
+    ///     .refresh_skew(60)
+    ///     .build();
+    /// assert_eq!(manager.token_ttl("key"), None);
+    ///
+    /// manager.get_client("key", "secret").await?;
+    /// assert_eq!(manager.token_ttl("key"), Some(Duration::from_secs(3_540)));
+    /// assert_eq!(manager.expires_at("key").unwrap().timestamp(), 4_600);
+    ///
+    /// clock.advance(1_800);
+    /// assert_eq!(manager.token_ttl("key"), Some(Duration::from_secs(1_740)));
+    /// assert_eq!(manager.token_age("key"), Some(Duration::from_secs(1_800)));
+    ///
+    /// // Within the skew the token is still valid but no longer served
+    /// clock.advance(1_750);
+    /// assert_eq!(manager.token_ttl("key"), None);
+    ///
+    /// clock.advance(3_600);
+    /// assert_eq!(manager.token_ttl("key"), None);
+    /// assert_eq!(manager.token_age("key"), Some(Duration::from_secs(7_150)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn token_ttl(&self, api_key: &str) -> Option<Duration> {
+        let key = self.key(api_key.to_string());
+        let now = self.inner.clock.instant();
+        let stale_at = self
+            .inner
+            .clients
+            .read()
+            .unwrap()
+            .peek(&key)?
+            .stale_at(self.inner.config.refresh_skew);
+
+        Some(stale_at.saturating_duration_since(now)).filter(|ttl| !ttl.is_zero())
+    }
+
+    /// How long ago the token cached for `api_key` was obtained, see [`issued_at`](Self::issued_at)
+    pub fn token_age(&self, api_key: &str) -> Option<Duration> {
+        let issued_at = self.issued_at(api_key)?;
+        let age = (self.inner.clock.now() - issued_at).max(0);
+
+        Some(Duration::from_secs(age as u64))
+    }
+
+    /// When the token cached for `api_key` expires, even if it expired already
+    pub fn expires_at(&self, api_key: &str) -> Option<DateTime<Utc>> {
+        let key = self.key(api_key.to_string());
+        let expires_at = self
+            .inner
+            .clients
+            .read()
+            .unwrap()
+            .peek(&key)?
+            .expiration_time();
+
+        Utc.timestamp_opt(expires_at, 0).single()
+    }
+
+    /// Whether [`get_cached_client`](Self::get_cached_client) would return a client
+    pub fn is_cached(&self, api_key: &str) -> bool {
+        self.get_cached_client(api_key).is_some()
+    }
+
+    /// Keeps the client for `api_key` refreshed in the background, shortly before it goes stale,
+    /// so that [`get_client`](Self::get_client) rarely has to wait for an authentication
+    ///
+    /// This holds on to `api_secret` until the client hasn't been requested for
+    /// [`KeepWarmConfig::idle_timeout`] seconds. Failed refreshes are retried with an
+    /// exponential backoff. Calling this again replaces the secret.
+    ///
+    /// The background task is spawned by the first call, which has to happen within
+    /// a Tokio runtime, and stops once the last clone of the manager is dropped.
+    /// Does nothing after a [`shutdown`](Self::shutdown).
+    pub fn keep_warm(&self, api_key: impl Into<ApiKey>, api_secret: impl Into<ApiSecret>) {
+        let key = self.key(api_key.into().into_inner());
+        let now = self.inner.clock.now();
+
+        // Checked under the lock, so that a concurrent shutdown can't miss a newly spawned task
+        let mut warmer = self.inner.warmer.lock().unwrap();
+        if self.inner.shut_down.load(Ordering::SeqCst) {
+            return;
+        }
+
+        self.inner.warm.lock().unwrap().insert(
+            key,
+            Warm {
+                api_secret: api_secret.into(),
+                last_requested: now,
+                failures: 0,
+                retry_at: now,
+            },
+        );
+
+        warmer.get_or_insert_with(|| {
+            spawn_warmer(
+                Arc::downgrade(&self.inner),
+                self.inner.config.keep_warm.interval,
+            )
+        });
+    }
+
+    /// Stops the background tasks, cancels authentications in progress, flushes the
+    /// [`TokenStore`] and drops all cached clients along with their connection pools,
+    /// e.g. when the process is asked to terminate
+    ///
+    /// Returns once the tasks have stopped. From then on every call for a client fails with
+    /// [`Error::ShutDown`], and [`keep_warm`](Self::keep_warm) does nothing. Callers waiting for
+    /// an authentication that was in progress fail with [`Error::ShutDown`] as well, rather than
+    /// holding up the shutdown until a possibly hung backend answers.
+    ///
+    /// Dropping the last clone of the manager without shutting it down only aborts the
+    /// background tasks, without waiting for them.
+    pub async fn shutdown(&self) {
+        let warmer = {
+            let mut warmer = self.inner.warmer.lock().unwrap();
+            self.inner.shut_down.store(true, Ordering::SeqCst);
+            warmer.take()
+        };
+        self.inner.stopped.send_replace(true);
+        let evictor = self.inner.evictor.lock().unwrap().take();
+
+        for task in [evictor, warmer].into_iter().flatten() {
+            task.abort();
+            // Only fails because the task was cancelled, or with a panic already reported by the runtime
+            let _ = task.await;
+        }
+
+        // No new authentication starts once the flag is set. A flight whose caller was cancelled
+        // is settled here instead of being left for someone to join.
+        let flights: Vec<_> = self
+            .inner
+            .in_flight
+            .lock()
+            .unwrap()
+            .values()
+            .map(|current| current.flight.clone())
+            .collect();
+        for flight in flights {
+            let _ = flight.get_or_init(|| async { Err(Error::ShutDown) }).await;
+        }
+
+        if let Some(store) = &self.inner.store {
+            if let Err(_err) = store.flush().await {
+                event!(WARN, error = %_err, "Failed to flush the token store");
+            }
+        }
+
+        self.inner.warm.lock().unwrap().clear();
+        // Unlike invalidating, this leaves the stored tokens for the next process
+        self.clear_cache();
+        event!(DEBUG, "Shut down");
+    }
+
+    /// How many background tasks are still running, 0 after a [`shutdown`](Self::shutdown)
+    pub fn background_tasks(&self) -> usize {
+        [&self.inner.evictor, &self.inner.warmer]
+            .iter()
+            .filter(|task| {
+                task.lock()
+                    .unwrap()
+                    .as_ref()
+                    .is_some_and(|task| !task.is_finished())
+            })
+            .count()
+    }
+
+    fn ensure_running(&self) -> Result<(), Error> {
+        if self.inner.shut_down.load(Ordering::SeqCst) {
+            return Err(Error::ShutDown);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the cached clients for `api_key`, of every context, app client and pool,
+    /// returning whether there were any
+    ///
+    /// The next call to [`get_client`](Self::get_client) for this key authenticates again,
+    /// even if the credentials were rejected recently. Clients handed out before keep working
+    /// with the old token, see `get_client`.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use existing_code_challenge::ClientManager;
+    ///
+    /// let manager = ClientManager::default();
+    /// let held = manager.get_client("key", "secret").await?;
+    ///
+    /// assert!(manager.invalidate("key").await);
+    /// assert!(!manager.is_cached("key"));
+    ///
+    /// // Still builds and sends requests, keeping the connection pool alive until it is dropped
+    /// let request = held.get("http://localhost/orders").build()?;
+    /// assert_eq!(request.url().path(), "/orders");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn invalidate(&self, api_key: &str) -> bool {
+        let other = |key: &CacheKey| key.api_key() != api_key;
+
+        self.inner
+            .in_flight
+            .lock()
+            .unwrap()
+            .retain(|key, _| other(key));
+        self.inner
+            .rejected
+            .lock()
+            .unwrap()
+            .retain(|key, _| other(key));
+        let removed = self
+            .inner
+            .clients
+            .write()
+            .unwrap()
+            .retain(|key, _| other(key));
+
+        let any = !removed.is_empty();
+
+        let mut auths = vec![self.inner.config.auth.clone()];
+        for key in &removed {
+            if !auths.contains(key.auth()) {
+                auths.push(key.auth().clone());
+            }
+        }
+        for auth in &auths {
+            self.remove_stored(auth, api_key).await;
+        }
+        self.inner.events.evicted(removed);
+
+        any
+    }
+
+    /// Like [`invalidate`](Self::invalidate), but only removes the client for exactly `key`
+    pub async fn invalidate_key(&self, key: &CacheKey) -> bool {
+        self.inner.in_flight.lock().unwrap().remove(key);
+        self.inner.rejected.lock().unwrap().remove(key);
+        let removed = self.inner.clients.write().unwrap().remove(key).is_some();
+
+        self.remove_stored(key.auth(), key.api_key()).await;
+        if removed {
+            self.inner.events.evicted([key.clone()]);
+        }
+        removed
+    }
+
+    /// Removes all cached clients, along with their tokens in the [`TokenStore`]
+    ///
+    /// Tokens stored by other processes for keys that aren't cached here are kept.
+    pub async fn invalidate_all(&self) {
+        for key in self.clear_cache() {
+            self.remove_stored(key.auth(), key.api_key()).await;
+        }
+    }
+
+    /// Removes all cached clients, returning their keys
+    fn clear_cache(&self) -> Vec<CacheKey> {
+        self.inner.in_flight.lock().unwrap().clear();
+        self.inner.rejected.lock().unwrap().clear();
+        let removed = self.inner.clients.write().unwrap().clear();
+
+        self.inner.events.evicted(removed.clone());
+        removed
+    }
+
+    async fn remove_stored(&self, auth: &AuthConfig, api_key: &str) {
+        if let Some(store) = &self.inner.store {
+            if let Err(_err) = store.remove(auth, api_key).await {
+                event!(WARN, error = %_err, "Failed to remove a stored token");
+            }
+        }
+    }
+
+    /// Subscribes to the events of every cached client, e.g. to hand each new token to a
+    /// connection that isn't made through the cached clients
+    ///
+    /// Events are sent once the manager released its locks and never wait for subscribers. One that
+    /// falls more than 64 events behind misses the oldest ones, and is told so by
+    /// [`RecvError::Lagged`](broadcast::error::RecvError::Lagged).
+    pub fn subscribe(&self) -> broadcast::Receiver<TokenEvent> {
+        self.inner.events.subscribe()
+    }
+
+    /// Whether authentications currently reach the backend, always closed if the
+    /// [`circuit_breaker`](ManagerConfig::circuit_breaker) is disabled
+    pub fn circuit_state(&self) -> CircuitState {
+        let now = self.inner.clock.now();
+
+        self.inner
+            .breaker
+            .as_ref()
+            .map_or(CircuitState::Closed, |breaker| breaker.state(now))
+    }
+
+    /// How many authentications of `api_key` may start before hitting the
+    /// [`rate_limit`](ManagerConfig::rate_limit), `None` if it's disabled
+    pub fn rate_limit_state(&self, api_key: &str) -> Option<RateLimitState> {
+        let limiter = self.inner.limiter.as_ref()?;
+        let key = self.key(api_key.to_owned());
+
+        Some(limiter.state(&key, self.inner.clock.now()))
+    }
+
+    /// Closes the circuit breaker, e.g. once the backend is known to have recovered
+    pub fn reset_circuit(&self) {
+        if let Some(breaker) = &self.inner.breaker {
+            breaker.reset();
+        }
+    }
+
+    /// Removes all cached clients, same as [`invalidate_all`](Self::invalidate_all)
+    pub async fn clear(&self) {
+        self.invalidate_all().await;
+    }
+
+    /// The maximum number of cached clients, if bounded
+    pub fn capacity(&self) -> Option<usize> {
+        self.inner.config.max_capacity
+    }
+
+    /// Upper bound of the idle connections to a single host across all cached clients, if
+    /// both the capacity and the idle connections per client are bounded
+    ///
+    /// See [`ClientConfig`](crate::ClientConfig#connection-pools) on how to tune it.
+    pub fn max_idle_connections_per_host(&self) -> Option<usize> {
+        let per_client = self.inner.config.client.pool_max_idle_per_host;
+        if per_client == usize::MAX {
+            return None;
+        }
+
+        self.capacity()?.checked_mul(per_client)
+    }
+
+    /// The number of currently cached clients, including expired ones not evicted yet
+    pub async fn len(&self) -> usize {
+        self.inner.clients.read().unwrap().len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    /// Describes every cached client, including expired ones not evicted yet, e.g. for an admin endpoint
+    pub async fn snapshot(&self) -> Vec<CacheEntryInfo> {
+        let now = self.inner.clock.instant();
+
+        self.inner.clients.read().unwrap().snapshot(now)
+    }
+
+    /// The api key and expiration time of every cached client, including expired ones not
+    /// evicted yet, in no particular order
+    ///
+    /// A key appears once per cache key it's cached under, e.g. with several [`AuthConfig`]s or
+    /// scopes. Unlike [`snapshot`](Self::snapshot) the api keys are returned in full, secrets
+    /// and tokens never are.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), existing_code_challenge::Error> {
+    /// use std::sync::Arc;
+    ///
+    /// use existing_code_challenge::{ClientManager, MockClock};
+    ///
+    /// let clock = MockClock::new(1_000);
+    /// let manager = ClientManager::builder()
+    ///     .clock(Arc::new(clock.clone()))
+    ///     .build();
+    ///
+    /// manager.get_client("first", "secret").await?;
+    /// clock.advance(100);
+    /// manager.get_client("second", "secret").await?;
+    ///
+    /// let mut entries = manager.entries().await;
+    /// entries.sort();
+    /// assert_eq!(
+    ///     entries,
+    ///     [("first".to_string(), 4_600), ("second".to_string(), 4_700)],
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn entries(&self) -> Vec<(String, i64)> {
+        self.inner
+            .clients
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(key, client)| (key.api_key().to_string(), client.expiration_time()))
+            .collect()
+    }
+
+    /// Counts the cached clients and evictions and finds the next expiry, without touching the
+    /// cache otherwise
+    pub async fn stats(&self) -> CacheStats {
+        let now = self.inner.clock.instant();
+
+        CacheStats {
+            evictions: self.evictions(),
+            ..self.inner.clients.read().unwrap().stats(now)
+        }
+    }
+
+    /// How many clients have been evicted so far to stay within `max_capacity`,
+    /// including still valid ones
+    pub fn evictions(&self) -> u64 {
+        self.inner.evictions.load(Ordering::Relaxed)
+    }
+
+    /// Removes clients that expired more than `eviction_grace` seconds ago,
+    /// returning how many were removed
+    pub async fn evict_expired(&self) -> usize {
+        self.inner.evict_expired()
+    }
+
+    fn key(&self, api_key: String) -> CacheKey {
+        self.cache_key(&self.inner.config.auth, ClientContext::new(api_key))
+    }
+
+    /// The key a client requested with `context` from `auth` is cached under
+    pub fn cache_key(&self, auth: &AuthConfig, context: ClientContext) -> CacheKey {
+        CacheKey::new(
+            auth.clone(),
+            context.api_key().as_str().to_string(),
+            self.inner.key_deriver.as_ref().map_or_else(
+                || by_api_key(&context),
+                |deriver| deriver.derive_key(&context),
+            ),
+            context.headers().clone(),
+        )
+    }
+
+    /// Refreshes every warm client that goes stale within the configured lead time
+    async fn refresh_warm(&self) {
+        let KeepWarmConfig {
+            lead,
+            idle_timeout,
+            max_backoff,
+            ..
+        } = self.inner.config.keep_warm;
+        let now = self.inner.clock.now();
+
+        let due: Vec<_> = {
+            let mut warm = self.inner.warm.lock().unwrap();
+            warm.retain(|_, warm| now - warm.last_requested <= idle_timeout);
+
+            warm.iter()
+                .filter(|(_, warm)| warm.retry_at <= now)
+                .map(|(key, warm)| (key.clone(), warm.api_secret.clone()))
+                .collect()
+        };
+
+        for (key, api_secret) in due {
+            let res = self
+                .refresh_stale(&key, &api_secret, offset(self.inner.clock.instant(), lead))
+                .await;
+
+            let mut warm = self.inner.warm.lock().unwrap();
+            let Some(warm) = warm.get_mut(&key) else {
+                continue;
+            };
+            match res {
+                Ok(_) => warm.failures = 0,
+                Err(_) => {
+                    warm.failures = warm.failures.saturating_add(1);
+                    warm.retry_at = now + (1i64 << warm.failures.min(32)).min(max_backoff);
+                }
+            }
+        }
+    }
+
+    /// Authenticates unless the client for `key` is still fresh at `at`, joining any authentication
+    /// already in progress
+    async fn refresh_stale(
+        &self,
+        key: &CacheKey,
+        api_secret: &ApiSecret,
+        at: Instant,
+    ) -> Result<Authorized, Error> {
+        let secret_hash = self.inner.secret_hasher.hash(api_secret);
+        if let Some(authorized) = self.fresh_at(key, &secret_hash, at) {
+            return Ok(authorized);
+        }
+
+        let (flight, _) = self.flight(key);
+        self.join(key, flight, || async {
+            if let Some(authorized) = self.fresh_at(key, &secret_hash, at) {
+                return Ok(authorized);
+            }
+
+            self.authenticate(key, api_secret, false).await
+        })
+        .await
+    }
+
+    /// Returns the cached client if it's still fresh at `at`, without marking it as used
+    fn fresh_at(
+        &self,
+        key: &CacheKey,
+        secret_hash: &SecretHash,
+        at: Instant,
+    ) -> Option<Authorized> {
+        let clients = self.inner.clients.read().unwrap();
+        let client = clients.peek(key)?;
+
+        (client.secret_hash == *secret_hash && client.is_fresh(at, self.inner.config.refresh_skew))
+            .then(|| client.authorized())
+    }
+
+    /// Returns the error of a recent rejection of the same credentials
+    fn rejected(&self, key: &CacheKey, secret_hash: &SecretHash) -> Option<Error> {
+        let now = self.inner.clock.now();
+
+        let mut rejected = self.inner.rejected.lock().unwrap();
+        let entry = rejected.get(key)?;
+        if entry.until <= now {
+            rejected.remove(key);
+            return None;
+        }
+
+        (entry.secret_hash == *secret_hash).then(|| entry.error.clone())
+    }
+
+    /// Returns the cached client, even if stale, if it may still be served after a failed refresh
+    fn stale(&self, key: &CacheKey, secret_hash: &SecretHash, now: Instant) -> Option<Authorized> {
+        let grace = self.inner.config.serve_stale_for?;
+
+        let clients = self.inner.clients.read().unwrap();
+        let client = clients.peek(key)?;
+
+        (client.secret_hash == *secret_hash && !client.is_expired(offset(now, -grace)))
+            .then(|| client.authorized())
+    }
+
+    /// Returns the cached client if it's fresh and was authenticated with the same secret
+    fn cached(&self, key: &CacheKey, secret_hash: &SecretHash) -> Option<Authorized> {
+        let now = self.inner.clock.instant();
+
+        let clients = self.inner.clients.read().unwrap();
+        let client = clients.get(key, now)?;
+
+        (client.secret_hash == *secret_hash && client.is_fresh(now, self.inner.config.refresh_skew))
+            .then(|| client.authorized())
+    }
+
+    /// Returns a fresh cached client for the api key of `key` whose token was granted at least
+    /// the scopes of `key`, whichever scopes it was requested with
+    fn covering(&self, key: &CacheKey, secret_hash: &SecretHash) -> Option<Authorized> {
+        let now = self.inner.clock.instant();
+        let skew = self.inner.config.refresh_skew;
+
+        let clients = self.inner.clients.read().unwrap();
+        let (covering, _) = clients.iter().find(|(candidate, client)| {
+            candidate.same_client(key)
+                && client.secret_hash == *secret_hash
+                && client.is_fresh(now, skew)
+                && client.covers(key.scopes())
+        })?;
+
+        // Looked up again to mark it as used
+        Some(clients.get(covering, now)?.authorized())
+    }
+
+    /// Waits for `flight` to complete, running `init` if no one else is, and then retires it
+    async fn join<F, Fut>(
+        &self,
+        key: &CacheKey,
+        flight: Arc<Flight>,
+        init: F,
+    ) -> Result<Authorized, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Authorized, Error>>,
+    {
+        let res = flight
+            .get_or_init(|| self.until_stopped(init()))
+            .await
+            .clone();
+
+        let mut in_flight = self.inner.in_flight.lock().unwrap();
+        if in_flight
+            .get(key)
+            .is_some_and(|current| Arc::ptr_eq(&current.flight, &flight))
+        {
+            in_flight.remove(key);
+        }
+
+        res
+    }
+
+    /// Runs `authentication` unless the manager is shut down before it completes
+    async fn until_stopped(
+        &self,
+        authentication: impl Future<Output = Result<Authorized, Error>>,
+    ) -> Result<Authorized, Error> {
+        let mut stopped = self.inner.stopped.subscribe();
+        let stopped = async move {
+            // The sender lives as long as the manager, so this never fails
+            while !*stopped.borrow_and_update() && stopped.changed().await.is_ok() {}
+        };
+
+        let (mut authentication, mut stopped) =
+            (std::pin::pin!(authentication), std::pin::pin!(stopped));
+        std::future::poll_fn(|cx| {
+            if let Poll::Ready(res) = authentication.as_mut().poll(cx) {
+                return Poll::Ready(res);
+            }
+
+            stopped.as_mut().poll(cx).map(|()| Err(Error::ShutDown))
+        })
+        .await
+    }
+
+    /// Returns the in-flight authentication for `key`, starting a new one if necessary, and
+    /// whether it's a forced refresh
+    fn flight(&self, key: &CacheKey) -> (Arc<Flight>, bool) {
+        let mut in_flight = self.inner.in_flight.lock().unwrap();
+        let current = in_flight.entry(key.clone()).or_insert_with(|| InFlight {
+            flight: Arc::default(),
+            forced: false,
+        });
+
+        // An already finished flight has either failed or its client has gone stale in the meantime
+        if current.flight.initialized() {
+            *current = InFlight {
+                flight: Arc::default(),
+                forced: false,
+            };
+        }
+
+        (current.flight.clone(), current.forced)
+    }
+
+    /// Authenticates and caches the resulting client
+    ///
+    /// A client cached in the meantime by an authentication that started later is kept and returned
+    /// instead, e.g. by a forced refresh with a rotated secret. Unless `force` is set, so is a
+    /// fresher client. Other clients authenticated with a different secret are always replaced,
+    /// as is their refresh token.
+    /// Tokens expiring sooner than `min_lifetime` are refused, ones that are already expired
+    /// by their JWT `exp` claim are returned without being cached.
+    ///
+    /// If the backend can't be reached and serving stale clients is enabled, the cached client
+    /// is returned instead of the error.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(
+            key = %crate::credentials::fingerprint(key.api_key()),
+            force = force,
+            elapsed_ms = tracing::field::Empty,
+        ))
+    )]
+    async fn authenticate(
+        &self,
+        key: &CacheKey,
+        api_secret: &ApiSecret,
+        force: bool,
+    ) -> Result<Authorized, Error> {
+        // Backstop for callers that checked just before a shutdown
+        self.ensure_running()?;
+        let now = self.inner.clock.now();
+        let instant = self.inner.clock.instant();
+        let api_key = key.api_key();
+        validate_api_key(&self.inner.config, api_key)?;
+        let secret_hash = self.inner.secret_hasher.hash(api_secret);
+        let generation = self.inner.generation.fetch_add(1, Ordering::Relaxed) + 1;
+
+        // Another process might have authenticated recently
+        if !force {
+            if let Some(authorized) = self
+                .restore(key, api_secret, secret_hash, now, generation)
+                .await
+            {
+                return Ok(authorized);
+            }
+        }
+
+        // A forced refresh is usually a reaction to a revoked token, so it always starts over
+        let mut refresh_token = if force {
+            None
+        } else {
+            self.inner
+                .clients
+                .read()
+                .unwrap()
+                .peek(key)
+                .filter(|client| client.secret_hash == secret_hash)
+                .and_then(|client| client.refresh_token.clone())
+                .filter(|refresh_token| !refresh_token.is_expired(now))
+        };
+
+        // Restoring from the store or serving a stale client isn't limited, only reaching the backend is
+        if let Some(limiter) = &self.inner.limiter {
+            let wait = limiter.acquire(key, now)?;
+            if !wait.is_zero() {
+                event!(
+                    DEBUG,
+                    wait_ms = wait.as_millis() as u64,
+                    "Waiting for the rate limit"
+                );
+                tokio::time::sleep(wait).await;
+            }
+        }
+
+        if let Some(breaker) = &self.inner.breaker {
+            if !breaker.admit(now) {
+                event!(DEBUG, "Circuit breaker is open");
+                if !force {
+                    if let Some(stale) = self.stale(key, &secret_hash, instant) {
+                        self.inner.metrics.on_stale_served();
+                        return Ok(stale);
+                    }
+                }
+
+                return Err(Error::CircuitOpen);
+            }
+        }
+
+        let start = std::time::Instant::now();
+        let mut refreshed = None;
+        if let Some(refresh_token) = &refresh_token {
+            refreshed = self
+                .bounded(self.inner.authenticator.refresh(
+                    key.auth(),
+                    api_key,
+                    &refresh_token.token,
+                ))
+                .await
+                .ok();
+        }
+
+        let res = match refreshed {
+            Some(res) => Ok(res),
+            None => {
+                // Fall back to a full authentication, the refresh token is of no further use
+                refresh_token = None;
+                self.authenticate_with_retry(key, api_secret).await
+            }
+        };
+
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("elapsed_ms", start.elapsed().as_millis() as u64);
+
+        if let Some(breaker) = &self.inner.breaker {
+            match &res {
+                Err(err) if self.is_retryable(err) => breaker.on_failure(now),
+                _ => breaker.on_success(),
+            }
+        }
+
+        let res = match res {
+            Ok(res) => {
+                self.inner.metrics.on_auth_success(start.elapsed());
+                event!(
+                    DEBUG,
+                    elapsed = ?start.elapsed(),
+                    expires_in = res.expires_in(),
+                    refreshed = refresh_token.is_some(),
+                    "Authenticated"
+                );
+                res
+            }
+            Err(err) => {
+                // Retrying rejected credentials right away is pointless, unlike a network error
+                let error_kind = self.classify(&err);
+                let retryable = error_kind == ErrorKind::Transient;
+                let kind = if retryable {
+                    FailureKind::Transient
+                } else {
+                    FailureKind::Rejected
+                };
+                self.inner.metrics.on_auth_failure(start.elapsed(), kind);
+                self.inner.events.emit(TokenEvent::RefreshFailed {
+                    key: key.clone(),
+                    kind,
+                });
+                #[cfg(feature = "tracing")]
+                log_failure(
+                    self.inner.config.failure_log_level,
+                    key,
+                    start.elapsed(),
+                    kind,
+                    &crate::credentials::redact(&format!("{err:#}"), api_secret),
+                );
+
+                let err = match err.downcast_ref::<TimedOut>() {
+                    Some(timed_out) => Error::AuthTimeout(timed_out.0),
+                    None => Error::authentication_failed(err, error_kind),
+                };
+
+                if retryable && !force {
+                    if let Some(stale) = self.stale(key, &secret_hash, instant) {
+                        self.inner.metrics.on_stale_served();
+                        event!(WARN, "Serving a stale client after a failed refresh");
+                        return Ok(stale);
+                    }
+                }
+
+                let ttl = self.inner.config.negative_ttl;
+                if !retryable && ttl > 0 {
+                    self.inner.rejected.lock().unwrap().insert(
+                        key.clone(),
+                        Rejected {
+                            error: err.clone(),
+                            secret_hash,
+                            until: now + ttl,
+                        },
+                    );
+                }
+
+                return Err(err);
+            }
+        };
+        self.inner.rejected.lock().unwrap().remove(key);
+
+        let scopes = match res.scope() {
+            Some(granted) => normalize_scopes(granted.iter().cloned()),
+            None => key.scopes().to_vec(),
+        };
+        let missing: Vec<String> = key
+            .scopes()
+            .iter()
+            .filter(|scope| scopes.binary_search(scope).is_err())
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            event!(WARN, ?missing, "Token was not granted the requested scopes");
+            return Err(Error::ScopeNotGranted(missing));
+        }
+
+        let expires_in = self.lifetime(res.expires_in())?;
+        let access_token = res.access_token();
+        let client = self.build_client(key, access_token)?;
+
+        #[allow(unused_mut)]
+        let mut expiration_time = now.saturating_add(expires_in);
+
+        // The issuer's clock is authoritative, `expires_in` may have been counted from an earlier issuance
+        #[cfg(feature = "jwt")]
+        if let Some(exp) = crate::jwt::expiration(access_token) {
+            expiration_time = expiration_time.min(exp);
+        }
+
+        // A token that is already expired would only waste a slot and be replaced on the next call
+        if expiration_time <= now {
+            let token = AccessToken::new(access_token, expiration_time);
+            return Ok(Authorized { client, token });
+        }
+
+        // Providers may keep the refresh token unchanged and not send it again
+        let refresh_token = res
+            .refresh_token()
+            .map(|token| RefreshToken {
+                token: token.to_string(),
+                expires_at: res
+                    .refresh_expires_in()
+                    .map(|expires_in| now.saturating_add(expires_in)),
+            })
+            .or(refresh_token);
+        let token = AccessToken::new(access_token, expiration_time);
+        let deadline = offset(instant, expiration_time - now);
+
+        if self.superseded(key, generation, secret_hash) {
+            event!(
+                DEBUG,
+                "Discarding a token of a secret that was rotated meanwhile"
+            );
+            return Ok(Authorized { client, token });
+        }
+
+        let evicted = {
+            let mut clients = self.inner.clients.write().unwrap();
+            if let Some(current) = clients.peek(key) {
+                // Don't clobber a newer or fresher client stored by a racer that finished first
+                let fresher =
+                    !force && current.secret_hash == secret_hash && current.deadline > deadline;
+                if current.generation > generation || fresher {
+                    event!(DEBUG, "Discarding a token older than the cached one");
+                    return Ok(current.authorized());
+                }
+            }
+
+            let entry = self.token_entry(
+                TokenEntry::new(token.clone(), now, deadline, refresh_token, secret_hash)
+                    .with_scopes(scopes),
+                api_secret,
+                generation,
+            );
+            clients.insert(
+                key.clone(),
+                ExpiringClient::new(client.clone(), entry),
+                instant,
+            )
+        };
+        self.record_evictions(evicted);
+        self.inner.events.emit(TokenEvent::Refreshed {
+            key: key.clone(),
+            expires_at: expiration_time,
+        });
+
+        // Stored tokens are identified by the api key alone, scoped ones would replace each other
+        if let Some(store) = self
+            .inner
+            .store
+            .as_ref()
+            .filter(|_| key.scopes().is_empty())
+        {
+            let stored = TokenEntry::new(token.clone(), now, deadline, None, secret_hash).stored();
+            if let Err(_err) = store.save(key.auth(), api_key, &stored).await {
+                event!(WARN, error = %_err, "Failed to persist token");
+            }
+        }
+
+        Ok(Authorized { client, token })
+    }
+
+    /// Checks the `expires_in` returned by the backend against the configured bounds
+    fn lifetime(&self, expires_in: i64) -> Result<i64, Error> {
+        let ManagerConfig {
+            min_lifetime,
+            max_lifetime,
+            ..
+        } = self.inner.config;
+
+        if expires_in < min_lifetime {
+            event!(WARN, expires_in, "Refusing a token that expires too soon");
+            return Err(Error::InvalidLifetime(expires_in));
+        }
+        if expires_in > max_lifetime {
+            self.inner.metrics.on_lifetime_clamped(expires_in);
+            event!(
+                WARN,
+                expires_in,
+                max_lifetime,
+                "Clamping the lifetime of a token"
+            );
+            return Ok(max_lifetime);
+        }
+
+        Ok(expires_in)
+    }
+
+    /// Caches the client for the token in the store, if there is a fresh one
+    ///
+    /// Tokens that can't be used are ignored, the caller authenticates as if there was none.
+    /// Scoped tokens are never stored.
+    async fn restore(
+        &self,
+        key: &CacheKey,
+        api_secret: &ApiSecret,
+        secret_hash: SecretHash,
+        now: i64,
+        generation: u64,
+    ) -> Option<Authorized> {
+        if !key.scopes().is_empty() {
+            return None;
+        }
+        let stored = self
+            .inner
+            .store
+            .as_ref()?
+            .load(key.auth(), key.api_key())
+            .await?;
+        let skew = self.inner.config.refresh_skew;
+        if !is_fresh(stored.issued_at, stored.expires_at, now, skew) {
+            return None;
+        }
+
+        // Tokens are shared, clients are built by every process for itself
+        let client = self.build_client(key, &stored.access_token).ok()?;
+        let token = AccessToken::new(&stored.access_token, stored.expires_at);
+        // Other processes only share the wall clock, whatever time it has left is counted from now
+        let instant = self.inner.clock.instant();
+        let deadline = offset(instant, stored.expires_at - now);
+        let entry = self.token_entry(
+            TokenEntry::new(token.clone(), stored.issued_at, deadline, None, secret_hash),
+            api_secret,
+            generation,
+        );
+        let entry = ExpiringClient::new(client.clone(), entry);
+
+        let evicted = {
+            let mut clients = self.inner.clients.write().unwrap();
+            // Loading took a while, a newer authentication may have finished meanwhile
+            if let Some(current) = clients
+                .peek(key)
+                .filter(|current| current.generation > generation)
+            {
+                event!(DEBUG, "Discarding a stored token older than the cached one");
+                return Some(current.authorized());
+            }
+
+            clients.insert(key.clone(), entry, instant)
+        };
+        self.record_evictions(evicted);
+        self.inner.events.emit(TokenEvent::Refreshed {
+            key: key.clone(),
+            expires_at: stored.expires_at,
+        });
+        event!(DEBUG, "Restored token from the store");
+
+        Some(Authorized { client, token })
+    }
+
+    /// Applies the configured jitter and credential retention to a new entry
+    fn token_entry(
+        &self,
+        entry: TokenEntry,
+        api_secret: &ApiSecret,
+        generation: u64,
+    ) -> TokenEntry {
+        let config = &self.inner.config;
+
+        entry
+            .with_jitter(
+                config.expiry_jitter,
+                config.expiry_jitter_percent,
+                &self.inner.jitter,
+            )
+            .with_generation(generation)
+            .with_secret(config.retain_credentials.then(|| api_secret.clone()))
+    }
+
+    fn record_evictions(&self, evicted: Vec<CacheKey>) {
+        self.inner
+            .evictions
+            .fetch_add(evicted.len() as u64, Ordering::Relaxed);
+        for _ in &evicted {
+            self.inner.metrics.on_eviction();
+        }
+        if !evicted.is_empty() {
+            event!(
+                DEBUG,
+                evicted = evicted.len(),
+                "Evicted clients over capacity"
+            );
+        }
+        self.inner.events.evicted(evicted);
+    }
+
+    /// Builds a client sending `access_token` as configured by the `auth_scheme`, along with the
+    /// headers of `key`, with every request
+    fn build_client(&self, key: &CacheKey, access_token: &str) -> Result<Client, Error> {
+        let headers = self.headers(key, access_token)?;
+        let config = &self.inner.config;
+
+        match config.client_mode {
+            ClientMode::PerToken => config
+                .client
+                .apply(Client::builder())
+                .and_then(|builder| builder.default_headers(headers).build())
+                .map_err(Error::client_build),
+            // Still validates the headers, so that a token is only cached if it can be sent
+            ClientMode::Shared => self
+                .inner
+                .shared
+                .get_or_try_init(|| {
+                    config
+                        .client
+                        .apply(Client::builder())
+                        .and_then(|builder| builder.build())
+                        .map_err(Error::client_build)
+                })
+                .cloned(),
+        }
+    }
+
+    /// The headers a request authorized with `access_token` carries, see [`auth_headers`]
+    fn headers(&self, key: &CacheKey, access_token: &str) -> Result<HeaderMap, Error> {
+        let mut extra = self.inner.config.extra_headers.clone();
+        extra.extend(key.headers().clone());
+
+        auth_headers(&self.inner.config, access_token, key.api_key(), extra)
+    }
+
+    async fn authenticate_with_retry(
+        &self,
+        key: &CacheKey,
+        api_secret: &ApiSecret,
+    ) -> anyhow::Result<AuthOutput> {
+        let Inner {
+            config,
+            authenticator,
+            ..
+        } = &*self.inner;
+
+        let mut attempt = 1;
+        loop {
+            let call = if key.scopes().is_empty() {
+                authenticator.authenticate(key.auth(), key.api_key(), api_secret)
+            } else {
+                authenticator.authenticate_scoped(
+                    key.auth(),
+                    key.api_key(),
+                    api_secret,
+                    key.scopes(),
+                )
+            };
+            match self.bounded(call).await {
+                // Timeouts are transient as well, the next attempt gets the full `auth_timeout` again
+                Err(err) if attempt < config.retry.max_attempts && self.is_retryable(&err) => {
+                    let retry_after = authenticator.retry_after(&err);
+                    tokio::time::sleep(config.retry.delay_after(attempt, retry_after)).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Fails with [`TimedOut`] if `call` takes longer than the configured `auth_timeout`
+    async fn bounded<T>(&self, call: impl Future<Output = anyhow::Result<T>>) -> anyhow::Result<T> {
+        let Some(timeout) = self.inner.config.auth_timeout else {
+            return call.await;
+        };
+
+        tokio::time::timeout(timeout, call)
+            .await
+            .unwrap_or_else(|_| Err(TimedOut(timeout).into()))
+    }
+
+    /// What a failed authentication is reported as, transient if it timed out and as
+    /// classified by the authenticator otherwise
+    fn classify(&self, err: &anyhow::Error) -> ErrorKind {
+        if err.is::<TimedOut>() {
+            return ErrorKind::Transient;
+        }
+
+        self.inner.authenticator.classify(err)
+    }
+
+    fn is_retryable(&self, err: &anyhow::Error) -> bool {
+        self.classify(err) == ErrorKind::Transient
+    }
+}
+
+/// A call to the authenticator that exceeded the `auth_timeout`
+#[derive(Debug, thiserror::Error)]
+#[error("Timed out after {0:?}")]
+struct TimedOut(Duration);
+
+/// Logs a failed authentication of `key` at `level`, with an `error` that was redacted already
+#[cfg(feature = "tracing")]
+fn log_failure(
+    level: crate::LogLevel,
+    key: &CacheKey,
+    elapsed: Duration,
+    kind: FailureKind,
+    error: &str,
+) {
+    let key = crate::credentials::fingerprint(key.api_key());
+    macro_rules! log {
+        ($level:ident) => {
+            tracing::event!(
+                tracing::Level::$level,
+                %key,
+                ?elapsed,
+                ?kind,
+                error,
+                "Authentication failed"
+            )
+        };
+    }
+
+    match level {
+        crate::LogLevel::Off => {}
+        crate::LogLevel::Error => log!(ERROR),
+        crate::LogLevel::Warn => log!(WARN),
+        crate::LogLevel::Info => log!(INFO),
+        crate::LogLevel::Debug => log!(DEBUG),
+        crate::LogLevel::Trace => log!(TRACE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{MockAuthenticator, MockClock, RetryPolicy};
+
+    fn manager(mock: &MockAuthenticator, clock: &MockClock) -> ClientManager {
+        ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(clock.clone()))
+            .config(ManagerConfig {
+                retry: RetryPolicy::none(),
+                ..ManagerConfig::default()
+            })
+            .build()
+    }
+
+    async fn get_concurrently(manager: &ClientManager, tasks: usize) -> Vec<Result<Client, Error>> {
+        let handles: Vec<_> = (0..tasks)
+            .map(|_| {
+                let manager = manager.clone();
+                tokio::spawn(async move { manager.get_client("key", "secret").await })
+            })
+            .collect();
+
+        let mut results = Vec::with_capacity(tasks);
+        for handle in handles {
+            results.push(handle.await.unwrap());
+        }
+        results
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_callers_share_one_authentication() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        mock.set_delay(Duration::from_secs(1));
+        let manager = manager(&mock, &clock);
+
+        let results = get_concurrently(&manager, 100).await;
+
+        assert!(results.iter().all(Result::is_ok));
+        mock.assert_calls(1);
+        assert!(manager.inner.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_callers_share_one_refresh_of_an_expired_key() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        mock.set_delay(Duration::from_secs(1));
+        let manager = manager(&mock, &clock);
+        manager.get_client("key", "secret").await.unwrap();
+        clock.advance(3_600);
+
+        let results = get_concurrently(&manager, 100).await;
+
+        assert!(results.iter().all(Result::is_ok));
+        mock.assert_calls(2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_authentication_reaches_every_waiter() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        mock.set_delay(Duration::from_secs(1));
+        mock.push_transient_error();
+        let manager = manager(&mock, &clock);
+
+        let results = get_concurrently(&manager, 10).await;
+
+        assert!(results.iter().all(|res| matches!(
+            res,
+            Err(Error::AuthenticationFailed {
+                kind: ErrorKind::Transient,
+                ..
+            })
+        )));
+        mock.assert_calls(1);
+        assert!(manager.is_empty().await);
+        assert!(manager.inner.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn call_after_a_failed_flight_authenticates_again() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        mock.set_delay(Duration::from_secs(1));
+        mock.push_transient_error();
+        let manager = manager(&mock, &clock);
+        assert!(get_concurrently(&manager, 10)
+            .await
+            .iter()
+            .all(Result::is_err));
+
+        manager.get_client("key", "secret").await.unwrap();
+
+        mock.assert_calls(2);
+        assert_eq!(manager.len().await, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keys_authenticate_concurrently() {
+        const KEYS: usize = 50;
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        mock.set_delay(Duration::from_secs(1));
+        let manager = manager(&mock, &clock);
+
+        let start = tokio::time::Instant::now();
+        let mut tasks = JoinSet::new();
+        for key in 0..KEYS {
+            for _ in 0..8 {
+                let manager = manager.clone();
+                tasks
+                    .spawn(async move { manager.get_client(format!("key-{key}"), "secret").await });
+            }
+        }
+        while let Some(res) = tasks.join_next().await {
+            res.unwrap().unwrap();
+        }
+
+        // One after another they would have taken 50 seconds
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+        mock.assert_calls(KEYS);
+        // The per-key flights are gone once the keys are authenticated
+        assert!(manager.inner.in_flight.lock().unwrap().is_empty());
+    }
+
+    /// Accepts only its current secret, and answers calls with the `slow` secret after a second
+    #[derive(Default)]
+    struct RotatingBackend {
+        secret: Mutex<String>,
+        slow: Mutex<Option<String>>,
+        calls: AtomicU64,
+    }
+
+    impl RotatingBackend {
+        fn new(secret: &str) -> Arc<Self> {
+            Arc::new(Self {
+                secret: Mutex::new(secret.to_string()),
+                ..Self::default()
+            })
+        }
+
+        fn rotate(&self, secret: &str) {
+            *self.secret.lock().unwrap() = secret.to_string();
+        }
+
+        fn calls(&self) -> u64 {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Authenticator for RotatingBackend {
+        async fn authenticate(
+            &self,
+            _config: &AuthConfig,
+            _api_key: &str,
+            api_secret: &ApiSecret,
+        ) -> anyhow::Result<AuthOutput> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            let secret = api_secret.expose_secret();
+            if self.slow.lock().unwrap().as_deref() == Some(secret) {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+
+            anyhow::ensure!(*self.secret.lock().unwrap() == secret, "invalid secret");
+            Ok(AuthOutput::new(format!("{secret}:{call}"), 3600))
+        }
+    }
+
+    fn rotating_manager(backend: &Arc<RotatingBackend>, clock: &MockClock) -> ClientManager {
+        ClientManager::builder()
+            .authenticator(backend.clone())
+            .clock(Arc::new(clock.clone()))
+            .config(ManagerConfig {
+                retry: RetryPolicy::none(),
+                ..ManagerConfig::default()
+            })
+            .build()
+    }
+
+    async fn token(manager: &ClientManager, secret: &str) -> Result<String, Error> {
+        let token = manager.get_token("key", secret).await?;
+
+        Ok(token.as_str().to_string())
+    }
+
+    #[tokio::test]
+    async fn rotation_survives_the_old_secret_failing() {
+        let (backend, clock) = (RotatingBackend::new("old"), MockClock::new(1_000));
+        let manager = rotating_manager(&backend, &clock);
+        assert_eq!(token(&manager, "old").await.unwrap(), "old:1");
+
+        // The old secret stops working midway, while the old client is still cached
+        backend.rotate("new");
+        assert_eq!(token(&manager, "old").await.unwrap(), "old:1");
+        manager.rotate_credentials("key", "new").await.unwrap();
+
+        // Callers still passing the old secret are served and refreshed with the new one
+        assert_eq!(token(&manager, "old").await.unwrap(), "new:2");
+        clock.advance(3_600);
+        assert_eq!(token(&manager, "old").await.unwrap(), "new:3");
+        assert_eq!(token(&manager, "new").await.unwrap(), "new:3");
+        assert_eq!(backend.calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn failed_rotation_keeps_the_old_secret() {
+        let (backend, clock) = (RotatingBackend::new("old"), MockClock::new(1_000));
+        let manager = rotating_manager(&backend, &clock);
+        token(&manager, "old").await.unwrap();
+
+        let err = manager.rotate_credentials("key", "new").await.unwrap_err();
+        assert!(matches!(err, Error::RotationFailed(_)));
+
+        assert_eq!(token(&manager, "old").await.unwrap(), "old:1");
+        clock.advance(3_600);
+        assert_eq!(token(&manager, "old").await.unwrap(), "old:3");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn authentication_with_the_old_secret_doesnt_undo_a_rotation() {
+        let (backend, clock) = (RotatingBackend::new("old"), MockClock::new(1_000));
+        *backend.slow.lock().unwrap() = Some("old".to_string());
+        let manager = rotating_manager(&backend, &clock);
+
+        let old = tokio::spawn({
+            let manager = manager.clone();
+            async move { token(&manager, "old").await }
+        });
+        tokio::task::yield_now().await;
+        backend.rotate("new");
+        manager.rotate_credentials("key", "new").await.unwrap();
+        manager.invalidate("key").await;
+        // The old secret was accepted before it stopped working
+        backend.rotate("old");
+        assert_eq!(old.await.unwrap().unwrap(), "old:1");
+        backend.rotate("new");
+
+        // Its token was returned to its caller, but not cached
+        assert!(manager.is_empty().await);
+        assert_eq!(token(&manager, "old").await.unwrap(), "new:3");
+    }
+
+    #[tokio::test]
+    async fn rotation_stores_the_secret_in_the_provider() {
+        let (backend, clock) = (RotatingBackend::new("old"), MockClock::new(1_000));
+        let manager = rotating_manager(&backend, &clock);
+        let provider = crate::StaticCredentials::new("key", "old");
+        manager.register_credentials("service", Arc::new(provider.clone()));
+        manager.get_client_by_name("service").await.unwrap();
+
+        backend.rotate("new");
+        manager.rotate_credentials("key", "new").await.unwrap();
+
+        let (_, secret) = provider.credentials().await.unwrap();
+        assert_eq!(secret.expose_secret(), "new");
+        clock.advance(3_600);
+        manager.get_client_by_name("service").await.unwrap();
+        assert_eq!(backend.calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn wall_clock_steps_dont_change_expiration() {
+        let (mock, clock) = (MockAuthenticator::new(3_600), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        manager.get_client("key", "secret").await.unwrap();
+
+        // A step forward past the expiration keeps the token
+        clock.set(100_000);
+        let (_, outcome) = manager.get_client_detailed("key", "secret").await.unwrap();
+        assert_eq!(outcome, CacheOutcome::Hit);
+        assert!(manager.is_cached("key"));
+        mock.assert_calls(1);
+
+        // A step back doesn't keep it once its time is up
+        clock.set(0);
+        clock.advance(3_600);
+        let (_, outcome) = manager.get_client_detailed("key", "secret").await.unwrap();
+        assert_eq!(outcome, CacheOutcome::Refreshed);
+        mock.assert_calls(2);
+    }
+
+    #[tokio::test]
+    async fn expires_at_follows_the_wall_clock() {
+        let (mock, clock) = (MockAuthenticator::new(3_600), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        manager.get_client("key", "secret").await.unwrap();
+
+        clock.set(100_000);
+        manager.force_refresh("key", "secret").await.unwrap();
+
+        assert_eq!(manager.expires_at("key").unwrap().timestamp(), 103_600);
+        assert_eq!(
+            manager.token_ttl("key").map(|ttl| ttl.as_secs()),
+            Some(3_600 - 60)
+        );
+    }
+
+    #[tokio::test]
+    async fn peek_returns_only_fresh_clients() {
+        let (mock, clock) = (MockAuthenticator::new(3_600), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        assert!(manager.peek("key").await.is_none());
+
+        manager.get_client("key", "secret").await.unwrap();
+        assert!(manager.peek("key").await.is_some());
+
+        // Stale within the refresh skew
+        clock.advance(3_600 - 30);
+        assert!(manager.peek("key").await.is_none());
+
+        clock.advance(30);
+        assert!(manager.peek("key").await.is_none());
+        mock.assert_calls(1);
+    }
+
+    #[tokio::test]
+    async fn get_cached_client_never_authenticates() {
+        let (mock, clock) = (MockAuthenticator::new(3_600), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        assert!(manager.get_cached_client("key").is_none());
+        mock.assert_calls(0);
+
+        manager.get_client("key", "secret").await.unwrap();
+        assert!(manager.get_cached_client("key").is_some());
+
+        clock.advance(3_600);
+        assert!(manager.get_cached_client("key").is_none());
+        assert!(manager.get_cached_client("key").is_none());
+        mock.assert_calls(1);
+    }
+
+    #[tokio::test]
+    async fn managers_sharing_a_store_authenticate_once() {
+        let (mock, clock) = (MockAuthenticator::new(3_600), MockClock::new(1_000));
+        let store = Arc::new(crate::MemoryTokenStore::new());
+        let manager = |mock: &MockAuthenticator| {
+            ClientManager::builder()
+                .authenticator(Arc::new(mock.clone()))
+                .clock(Arc::new(clock.clone()))
+                .token_store(store.clone())
+                .build()
+        };
+
+        let first = manager(&mock).get_token("key", "secret").await.unwrap();
+        let second = manager(&mock).get_token("key", "secret").await.unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(store.len(), 1);
+        mock.assert_calls(1);
+    }
+
+    /// Loads a fixed token, taking a second
+    struct SlowStore;
+
+    #[async_trait::async_trait]
+    impl TokenStore for SlowStore {
+        async fn load(&self, _auth: &AuthConfig, _api_key: &str) -> Option<crate::StoredToken> {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            Some(crate::StoredToken {
+                access_token: "stored".to_string(),
+                issued_at: 1_000,
+                expires_at: 4_600,
+            })
+        }
+
+        async fn save(
+            &self,
+            _auth: &AuthConfig,
+            _api_key: &str,
+            _token: &crate::StoredToken,
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn remove(&self, _auth: &AuthConfig, _api_key: &str) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_restore_doesnt_overwrite_a_newer_token() {
+        let (mock, clock) = (MockAuthenticator::new(3_600), MockClock::new(1_000));
+        mock.push_token("new", 3_600);
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(clock.clone()))
+            .token_store(Arc::new(SlowStore))
+            .build();
+
+        let restoring = tokio::spawn({
+            let manager = manager.clone();
+            async move { manager.get_token("key", "secret").await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        manager.force_refresh("key", "secret").await.unwrap();
+
+        assert_eq!(restoring.await.unwrap().unwrap().as_str(), "new");
+        assert_eq!(
+            manager.get_token("key", "secret").await.unwrap().as_str(),
+            "new"
+        );
+        mock.assert_calls(1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_stale_authentication_doesnt_overwrite_a_newer_token() {
+        let (mock, clock) = (MockAuthenticator::new(3_600), MockClock::new(1_000));
+        mock.push_token("old", 3_600).push_token("new", 3_600);
+        let manager = manager(&mock, &clock);
+
+        mock.set_delay(Duration::from_secs(2));
+        let stale = tokio::spawn({
+            let manager = manager.clone();
+            async move { manager.get_token("key", "secret").await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Finishes while the first authentication is still waiting for its response
+        mock.set_delay(Duration::ZERO);
+        manager.force_refresh("key", "secret").await.unwrap();
+        assert_eq!(
+            manager.get_token("key", "secret").await.unwrap().as_str(),
+            "new"
+        );
+
+        // The late writer gets the newer token instead of replacing it
+        assert_eq!(stale.await.unwrap().unwrap().as_str(), "new");
+        assert_eq!(
+            manager.get_token("key", "secret").await.unwrap().as_str(),
+            "new"
+        );
+        mock.assert_calls(2);
+    }
+
+    fn timing_out_manager(mock: &MockAuthenticator) -> ClientManager {
+        ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(MockClock::new(1_000)))
+            .config(ManagerConfig {
+                auth_timeout: Some(Duration::from_secs(1)),
+                retry: RetryPolicy {
+                    max_attempts: 3,
+                    base_delay: Duration::from_secs(1),
+                    multiplier: 1.0,
+                    jitter: false,
+                    ..RetryPolicy::default()
+                },
+                ..ManagerConfig::default()
+            })
+            .build()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timed_out_authentications_are_retried() {
+        let mock = MockAuthenticator::default();
+        mock.set_delay(Duration::from_secs(60));
+        let manager = timing_out_manager(&mock);
+
+        let start = Instant::now();
+        let err = manager.get_client("key", "secret").await.unwrap_err();
+
+        assert!(matches!(err, Error::AuthTimeout(timeout) if timeout == Duration::from_secs(1)));
+        assert_eq!(err.kind(), ErrorKind::Transient);
+        // Three timeouts with a second of backoff in between
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+        mock.assert_calls(3);
+        assert!(manager.inner.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_after_a_timeout_can_succeed() {
+        let mock = MockAuthenticator::default();
+        mock.set_delay(Duration::from_secs(60));
+        let manager = timing_out_manager(&mock);
+
+        let waiting = tokio::spawn({
+            let manager = manager.clone();
+            async move { manager.get_token("key", "secret").await }
+        });
+        // After the first attempt timed out, before the retry starts
+        tokio::time::sleep(Duration::from_millis(1_500)).await;
+        mock.set_delay(Duration::ZERO);
+
+        assert_eq!(waiting.await.unwrap().unwrap().as_str(), "mock:key:2");
+        mock.assert_calls(2);
+    }
+
+    fn lifetime_manager(mock: &MockAuthenticator, config: ManagerConfig) -> ClientManager {
+        ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(MockClock::new(1_000)))
+            .config(ManagerConfig {
+                retry: RetryPolicy::none(),
+                negative_ttl: 0,
+                ..config
+            })
+            .build()
+    }
+
+    #[tokio::test]
+    async fn zero_and_negative_lifetimes_are_refused() {
+        let mock = MockAuthenticator::default();
+        mock.push_token("zero", 0).push_token("negative", -5);
+        let manager = lifetime_manager(&mock, ManagerConfig::default());
+
+        let err = manager.get_client("key", "secret").await.unwrap_err();
+        assert!(matches!(err, Error::InvalidLifetime(0)));
+        let err = manager.get_client("key", "secret").await.unwrap_err();
+        assert!(matches!(err, Error::InvalidLifetime(-5)));
+        assert!(!manager.is_cached("key"));
+    }
+
+    #[tokio::test]
+    async fn zero_lifetime_token_is_returned_but_not_cached_without_a_minimum() {
+        let mock = MockAuthenticator::default();
+        mock.push_token("zero", 0);
+        let manager = lifetime_manager(
+            &mock,
+            ManagerConfig {
+                min_lifetime: 0,
+                ..ManagerConfig::default()
+            },
+        );
+
+        let token = manager.get_token("key", "secret").await.unwrap();
+        assert_eq!(token.as_str(), "zero");
+        assert!(!manager.is_cached("key"));
+
+        manager.get_client("key", "secret").await.unwrap();
+        mock.assert_calls(2);
+    }
+
+    #[tokio::test]
+    async fn huge_lifetime_is_clamped_to_the_maximum() {
+        let mock = MockAuthenticator::default();
+        mock.push_token("forever", i64::MAX);
+        let manager = lifetime_manager(&mock, ManagerConfig::default());
+
+        manager.get_client("key", "secret").await.unwrap();
+
+        let expires_at = manager.expires_at("key").unwrap().timestamp();
+        assert_eq!(expires_at, 1_000 + ManagerConfig::default().max_lifetime);
+    }
+
+    #[tokio::test]
+    async fn lifetime_above_max_lifetime_is_clamped() {
+        let (mock, clock) = (MockAuthenticator::new(3_600), MockClock::new(1_000));
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(clock.clone()))
+            .config(ManagerConfig {
+                max_lifetime: 600,
+                ..ManagerConfig::default()
+            })
+            .build();
+
+        manager.get_client("key", "secret").await.unwrap();
+        assert_eq!(manager.expires_at("key").unwrap().timestamp(), 1_600);
+
+        clock.advance(600);
+        manager.get_client("key", "secret").await.unwrap();
+        mock.assert_calls(2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_fails_callers_waiting_for_an_authentication() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        mock.set_delay(Duration::from_secs(60));
+        let manager = manager(&mock, &clock);
+
+        let waiting = tokio::spawn({
+            let manager = manager.clone();
+            async move { get_concurrently(&manager, 5).await }
+        });
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        mock.assert_calls(1);
+
+        let start = Instant::now();
+        manager.shutdown().await;
+
+        // Without waiting for the backend to answer
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        let results = waiting.await.unwrap();
+        assert!(results
+            .iter()
+            .all(|res| matches!(res, Err(Error::ShutDown))));
+        assert!(manager.inner.in_flight.lock().unwrap().is_empty());
+
+        let err = manager.get_client("key", "secret").await.unwrap_err();
+        assert!(matches!(err, Error::ShutDown));
+        mock.assert_calls(1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_stops_the_background_tasks() {
+        let (mock, clock) = (MockAuthenticator::new(600), MockClock::new(1_000));
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(clock.clone()))
+            .config(ManagerConfig {
+                eviction_interval: Some(Duration::from_secs(60)),
+                ..ManagerConfig::default()
+            })
+            .build();
+        manager.keep_warm("key", "secret");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        mock.assert_calls(1);
+        assert_eq!(manager.background_tasks(), 2);
+
+        manager.shutdown().await;
+        assert_eq!(manager.background_tasks(), 0);
+        assert!(manager.is_empty().await);
+
+        // Nothing is refreshed anymore once the token goes stale
+        clock.advance(3_600);
+        tokio::time::sleep(Duration::from_secs(3_600)).await;
+        mock.assert_calls(1);
+
+        // Nor does asking again spawn a task
+        manager.keep_warm("key", "secret");
+        assert_eq!(manager.background_tasks(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn transient_failures_are_retried_with_backoff() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        mock.push_transient_error()
+            .push_transient_error()
+            .push_token("token", 3_600);
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(clock.clone()))
+            .config(ManagerConfig {
+                retry: RetryPolicy {
+                    max_attempts: 3,
+                    base_delay: Duration::from_secs(1),
+                    multiplier: 2.0,
+                    jitter: false,
+                    ..RetryPolicy::default()
+                },
+                ..ManagerConfig::default()
+            })
+            .build();
+
+        let start = Instant::now();
+        let token = manager.get_token("key", "secret").await.unwrap();
+
+        assert_eq!(token.as_str(), "token");
+        // Backoff of one and then two seconds
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+        mock.assert_calls(3);
+        assert_eq!(manager.len().await, 1);
+        assert!(manager.is_cached("key"));
+    }
+
+    #[tokio::test]
+    async fn rejections_are_cached_for_the_negative_ttl() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        mock.set_failing(true);
+        let manager = manager(&mock, &clock);
+        assert_eq!(manager.config().negative_ttl, 10);
+
+        for _ in 0..20 {
+            let err = manager.get_client("key", "secret").await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Rejected);
+        }
+        mock.assert_calls(1);
+
+        clock.advance(9);
+        manager.get_client("key", "secret").await.unwrap_err();
+        mock.assert_calls(1);
+
+        clock.advance(1);
+        manager.get_client("key", "secret").await.unwrap_err();
+        mock.assert_calls(2);
+
+        // Cleared by invalidating
+        manager.invalidate("key").await;
+        mock.set_failing(false);
+        manager.get_client("key", "secret").await.unwrap();
+        mock.assert_calls(3);
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_not_cached() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        mock.fail_next(1);
+        let manager = manager(&mock, &clock);
+
+        let err = manager.get_client("key", "secret").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Transient);
+        manager.get_client("key", "secret").await.unwrap();
+        mock.assert_calls(2);
+    }
+
+    #[tokio::test]
+    async fn evictions_over_capacity_are_counted() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let metrics = Arc::new(crate::AtomicMetrics::new());
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(clock.clone()))
+            .metrics(metrics.clone())
+            .max_capacity(2)
+            .build();
+
+        manager.get_client("a", "secret").await.unwrap();
+        manager.get_client("b", "secret").await.unwrap();
+        // Touched after `b`, so `b` is the least recently used one
+        manager.get_client("a", "secret").await.unwrap();
+        assert_eq!(manager.stats().await.evictions, 0);
+
+        manager.get_client("c", "secret").await.unwrap();
+
+        assert!(!manager.is_cached("b"));
+        assert!(manager.is_cached("a") && manager.is_cached("c"));
+        assert_eq!(manager.stats().await.evictions, 1);
+        assert_eq!(manager.evictions(), 1);
+        assert_eq!(metrics.evictions(), 1);
+    }
+
+    #[tokio::test]
+    async fn metrics_count_hits_misses_and_failures() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let metrics = Arc::new(crate::AtomicMetrics::new());
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(clock.clone()))
+            .metrics(metrics.clone())
+            .config(ManagerConfig {
+                retry: RetryPolicy::none(),
+                ..ManagerConfig::default()
+            })
+            .build();
+
+        manager.get_client("a", "secret").await.unwrap();
+        manager.get_client("a", "secret").await.unwrap();
+        mock.push_transient_error();
+        manager.get_client("b", "secret").await.unwrap_err();
+        mock.push_rejection();
+        manager.get_client("c", "secret").await.unwrap_err();
+
+        assert_eq!(metrics.cache_hits(), 1);
+        assert_eq!(metrics.cache_misses(), 3);
+        assert_eq!(metrics.auth_successes(), 1);
+        assert_eq!(metrics.auth_failures(), 2);
+        assert_eq!(metrics.rejections(), 1);
+    }
+
+    #[tokio::test]
+    async fn subscribers_see_refreshes_failures_and_evictions() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        let mut events = manager.subscribe();
+
+        manager.get_client("a", "secret").await.unwrap();
+        mock.push_transient_error();
+        manager.get_client("b", "secret").await.unwrap_err();
+        manager.invalidate("a").await;
+
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            TokenEvent::Refreshed { key, expires_at: 4_600 } if key.api_key() == "a"
+        ));
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            TokenEvent::RefreshFailed { key, kind: FailureKind::Transient } if key.api_key() == "b"
+        ));
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            TokenEvent::Evicted { key } if key.api_key() == "a"
+        ));
+
+        manager.get_client("c", "secret").await.unwrap();
+        clock.advance(3_601);
+        assert_eq!(manager.evict_expired().await, 1);
+
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            TokenEvent::Refreshed { key, .. } if key.api_key() == "c"
+        ));
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            TokenEvent::Evicted { key } if key.api_key() == "c"
+        ));
+        assert!(events.try_recv().is_err());
+    }
+
+    fn cached_token(manager: &ClientManager, api_key: &str) -> Option<String> {
+        cached_token_for(manager, &manager.inner.config.auth, api_key)
+    }
+
+    fn cached_token_for(
+        manager: &ClientManager,
+        auth: &AuthConfig,
+        api_key: &str,
+    ) -> Option<String> {
+        let key = manager.cache_key(auth, ClientContext::new(api_key));
+        let clients = manager.inner.clients.read().unwrap();
+
+        Some(clients.peek(&key)?.token.as_str().to_string())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keep_warm_refreshes_stale_clients_without_a_caller() {
+        let (mock, clock) = (MockAuthenticator::new(600), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        manager.keep_warm("key", "secret");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        mock.assert_calls(1);
+        let token = cached_token(&manager, "key").unwrap();
+
+        // Past the stale_at of 540 seconds
+        clock.advance(541);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        mock.assert_calls(2);
+        assert_ne!(cached_token(&manager, "key").unwrap(), token);
+        assert_eq!(manager.issued_at("key"), Some(1_541));
+    }
+
+    #[tokio::test]
+    async fn changed_secret_reauthenticates_a_fresh_client() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        let old = token(&manager, "old").await.unwrap();
+        assert_eq!(token(&manager, "old").await.unwrap(), old);
+        mock.assert_calls(1);
+
+        let new = token(&manager, "new").await.unwrap();
+
+        mock.assert_calls(2);
+        assert_ne!(new, old);
+        assert_eq!(cached_token(&manager, "key"), Some(new));
+    }
+
+    /// Holds every authentication until the test lets it pass, keeping track of how many wait
+    struct GatedBackend {
+        gate: Semaphore,
+        in_flight: std::sync::atomic::AtomicUsize,
+        max_in_flight: std::sync::atomic::AtomicUsize,
+        rejected: Mutex<Vec<String>>,
+    }
+
+    impl GatedBackend {
+        fn new() -> Arc<Self> {
+            Arc::new(Self {
+                gate: Semaphore::new(0),
+                in_flight: Default::default(),
+                max_in_flight: Default::default(),
+                rejected: Mutex::default(),
+            })
+        }
+
+        fn in_flight(&self) -> usize {
+            self.in_flight.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Authenticator for GatedBackend {
+        async fn authenticate(
+            &self,
+            _config: &AuthConfig,
+            api_key: &str,
+            _api_secret: &ApiSecret,
+        ) -> anyhow::Result<AuthOutput> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            self.gate.acquire().await?.forget();
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            let rejected = self
+                .rejected
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|key| key == api_key);
+            anyhow::ensure!(!rejected, "invalid secret");
+            Ok(AuthOutput::new(format!("{api_key}:token"), 3600))
+        }
+    }
+
+    #[tokio::test]
+    async fn refresh_all_expired_limits_the_concurrent_refreshes() {
+        let (backend, clock) = (GatedBackend::new(), MockClock::new(1_000));
+        let manager = ClientManager::builder()
+            .authenticator(backend.clone())
+            .clock(Arc::new(clock.clone()))
+            .config(ManagerConfig {
+                retry: RetryPolicy::none(),
+                retain_credentials: true,
+                refresh_concurrency: 2,
+                ..ManagerConfig::default()
+            })
+            .build();
+        let keys = ["a", "b", "c", "d", "e"];
+        backend.gate.add_permits(keys.len());
+        for key in keys {
+            manager.get_client(key, "secret").await.unwrap();
+        }
+        backend.max_in_flight.store(0, Ordering::SeqCst);
+        backend.rejected.lock().unwrap().push("e".to_string());
+        clock.advance(3_600);
+
+        let refresh = tokio::spawn({
+            let manager = manager.clone();
+            async move { manager.refresh_all_expired().await }
+        });
+        while backend.in_flight() < 2 {
+            tokio::task::yield_now().await;
+        }
+        // The other refreshes wait for a permit rather than the backend
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(backend.in_flight(), 2);
+        backend.gate.add_permits(keys.len());
+        let results = refresh.await.unwrap();
+
+        assert_eq!(backend.max_in_flight.load(Ordering::SeqCst), 2);
+        assert_eq!(results.len(), keys.len());
+        for key in ["a", "b", "c", "d"] {
+            assert!(results[key].is_ok(), "{key}");
+        }
+        assert!(results["e"].is_err());
+    }
+
+    #[tokio::test]
+    async fn stale_clients_are_served_within_the_grace_window() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let metrics = Arc::new(crate::AtomicMetrics::new());
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(clock.clone()))
+            .metrics(metrics.clone())
+            .config(ManagerConfig {
+                retry: RetryPolicy::none(),
+                serve_stale_for: Some(300),
+                ..ManagerConfig::default()
+            })
+            .build();
+        manager.get_client("key", "secret").await.unwrap();
+
+        // 100 seconds past the expiration
+        clock.advance(3_700);
+        mock.push_transient_error();
+        manager.get_client("key", "secret").await.unwrap();
+        mock.assert_calls(2);
+        assert_eq!(metrics.stale_served(), 1);
+
+        // Past the grace window
+        clock.advance(200);
+        mock.push_transient_error();
+        let err = manager.get_client("key", "secret").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Transient);
+        mock.assert_calls(3);
+        assert_eq!(metrics.stale_served(), 1);
+    }
+
+    /// Collects everything logged on this thread until the guard is dropped
+    #[cfg(feature = "tracing")]
+    fn capture_logs() -> (Arc<Mutex<Vec<u8>>>, tracing::subscriber::DefaultGuard) {
+        #[derive(Clone)]
+        struct Writer(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Writer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let writer = Writer(logs.clone());
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        (logs, tracing::subscriber::set_default(subscriber))
+    }
+
+    #[cfg(feature = "tracing")]
+    fn logged(logs: &Mutex<Vec<u8>>) -> String {
+        String::from_utf8(logs.lock().unwrap().clone()).unwrap()
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn logs_identify_the_key_by_its_fingerprint_only() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        let (logs, _guard) = capture_logs();
+
+        manager
+            .get_client("key-0123456789", "hunter2")
+            .await
+            .unwrap();
+
+        let logs = logged(&logs);
+        assert!(logs.contains("Authenticated"), "{logs}");
+        assert!(logs.contains(&crate::credentials::fingerprint("key-0123456789")));
+        assert!(!logs.contains("hunter2"), "{logs}");
+        assert!(!logs.contains("key-0123456789"), "{logs}");
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn logged_failures_never_echo_the_secret() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        mock.push_error(anyhow::anyhow!("secret hunter2 is not valid for key"));
+        let (logs, _guard) = capture_logs();
+
+        manager
+            .get_client("key-0123456789", "hunter2")
+            .await
+            .unwrap_err();
+
+        let logs = logged(&logs);
+        assert!(logs.contains("Authentication failed"), "{logs}");
+        assert!(logs.contains("is not valid for key"), "{logs}");
+        assert!(!logs.contains("hunter2"), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn scoped_clients_cover_any_subset_in_any_order() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        let scoped = |scopes: &'static [&'static str]| {
+            manager.get_client_with_scope("key", "secret", scopes.iter().copied())
+        };
+
+        scoped(&["write", "read"]).await.unwrap();
+        scoped(&["read", "write"]).await.unwrap();
+        scoped(&["read"]).await.unwrap();
+        mock.assert_calls(1);
+        assert_eq!(manager.len().await, 1);
+
+        // Neither a disjoint set nor a superset is covered
+        scoped(&["admin"]).await.unwrap();
+        mock.assert_calls(2);
+        scoped(&["read", "write", "delete"]).await.unwrap();
+        mock.assert_calls(3);
+        assert_eq!(manager.len().await, 3);
+    }
+
+    #[tokio::test]
+    async fn tokens_missing_a_scope_are_not_cached() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        mock.push_output(AuthOutput::new("token", 3600).with_scope(["write", "read"]));
+        mock.push_output(AuthOutput::new("token", 3600).with_scope(["read"]));
+
+        // Granting the scopes in another order is fine
+        manager
+            .get_client_with_scope("key", "secret", ["read", "write"])
+            .await
+            .unwrap();
+        let err = manager
+            .get_client_with_scope("key", "secret", ["read", "admin"])
+            .await
+            .unwrap_err();
+
+        assert!(matches!(&err, Error::ScopeNotGranted(missing) if missing == &["admin"]));
+        assert_eq!(err.kind(), ErrorKind::Rejected);
+        assert_eq!(manager.len().await, 1);
+    }
+
+    #[tokio::test]
+    async fn snapshot_reports_inserts_hits_and_evictions() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(clock.clone()))
+            .max_capacity(2)
+            .build();
+        let entry = |snapshot: &[CacheEntryInfo], api_key: &str| {
+            let key_id = crate::credentials::fingerprint(api_key);
+            snapshot.iter().find(|info| info.key_id == key_id).cloned()
+        };
+
+        manager.get_client("a", "secret").await.unwrap();
+        manager.get_client("b", "secret").await.unwrap();
+        let snapshot = manager.snapshot().await;
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(entry(&snapshot, "a").unwrap().hits, 0);
+
+        manager.get_client("a", "secret").await.unwrap();
+        manager.get_client("a", "secret").await.unwrap();
+        clock.advance(100);
+        manager.get_client("c", "secret").await.unwrap();
+
+        let snapshot = manager.snapshot().await;
+        assert_eq!(snapshot.len(), 2);
+        let a = entry(&snapshot, "a").unwrap();
+        assert_eq!((a.hits, a.refreshed_at, a.remaining), (2, 1_000, 3_500));
+        let c = entry(&snapshot, "c").unwrap();
+        assert_eq!((c.hits, c.refreshed_at, c.expires_at), (0, 1_100, 4_700));
+        assert_eq!(entry(&snapshot, "b"), None);
+
+        let stats = CacheStats {
+            total: 2,
+            expired: 0,
+            next_expiry: Some(4_600),
+            evictions: 1,
+        };
+        assert_eq!(manager.stats().await, stats);
+        clock.advance(3_500);
+        assert_eq!(
+            manager.stats().await,
+            CacheStats {
+                expired: 1,
+                next_expiry: Some(4_700),
+                ..stats
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn the_same_jitter_seed_gives_the_same_stale_at() {
+        let ttls = |seed| async move {
+            let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+            let manager = ClientManager::builder()
+                .authenticator(Arc::new(mock))
+                .clock(Arc::new(clock))
+                .config(ManagerConfig {
+                    expiry_jitter: 900,
+                    jitter_seed: Some(seed),
+                    ..ManagerConfig::default()
+                })
+                .build();
+
+            let mut ttls = Vec::new();
+            for api_key in ["a", "b", "c", "d"] {
+                manager.get_client(api_key, "secret").await.unwrap();
+                ttls.push(manager.token_ttl(api_key).unwrap());
+            }
+            ttls
+        };
+
+        let seeded = ttls(7).await;
+        assert_eq!(ttls(7).await, seeded);
+        assert_ne!(ttls(8).await, seeded);
+        assert!(seeded.iter().any(|ttl| *ttl != seeded[0]));
+    }
+
+    #[tokio::test]
+    async fn managers_with_different_configs_dont_share_clients() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let with_auth = |auth| {
+            ClientManager::builder()
+                .authenticator(Arc::new(mock.clone()))
+                .clock(Arc::new(clock.clone()))
+                .config(ManagerConfig {
+                    auth,
+                    ..ManagerConfig::default()
+                })
+                .build()
+        };
+        let staging = with_auth(AuthConfig::new("client", "staging"));
+        let prod = with_auth(AuthConfig::new("client", "prod"));
+
+        staging.get_client("key", "secret").await.unwrap();
+        prod.get_client("key", "secret").await.unwrap();
+        mock.assert_calls(2);
+
+        staging.invalidate("key").await;
+        assert!(!staging.is_cached("key"));
+        assert!(prod.is_cached("key"));
+        prod.get_client("key", "secret").await.unwrap();
+        mock.assert_calls(2);
+    }
+
+    #[tokio::test]
+    async fn the_same_api_key_gets_a_client_per_auth_config() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        let (first, second) = (AuthConfig::new("a", "pool"), AuthConfig::new("b", "pool"));
+
+        manager
+            .get_client_for(&first, "key", "secret")
+            .await
+            .unwrap();
+        manager
+            .get_client_for(&second, "key", "secret")
+            .await
+            .unwrap();
+        manager
+            .get_client_for(&first, "key", "secret")
+            .await
+            .unwrap();
+
+        mock.assert_calls(2);
+        assert_ne!(
+            cached_token_for(&manager, &first, "key"),
+            cached_token_for(&manager, &second, "key")
+        );
+    }
+
+    #[tokio::test]
+    async fn shared_clients_keep_a_token_per_pool() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(clock.clone()))
+            .config(ManagerConfig {
+                client_mode: ClientMode::Shared,
+                ..ManagerConfig::default()
+            })
+            .build();
+        let pools = [
+            AuthConfig::new("client", "staging"),
+            AuthConfig::new("client", "prod"),
+        ];
+
+        for _ in 0..2 {
+            for auth in &pools {
+                manager.get_client_for(auth, "key", "secret").await.unwrap();
+            }
+        }
+
+        mock.assert_calls(2);
+        let mut pool_ids: Vec<_> = manager
+            .snapshot()
+            .await
+            .into_iter()
+            .map(|info| info.pool_id)
+            .collect();
+        pool_ids.sort();
+        assert_eq!(pool_ids, ["prod", "staging"]);
+        assert_ne!(
+            cached_token_for(&manager, &pools[0], "key"),
+            cached_token_for(&manager, &pools[1], "key")
+        );
+    }
+
+    #[tokio::test]
+    async fn staging_and_prod_clients_of_one_key_are_independent() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        let staging = AuthConfig::new("client", "staging");
+        let prod = AuthConfig::new("client", "prod");
+        manager
+            .get_client_for(&staging, "key", "secret")
+            .await
+            .unwrap();
+        manager
+            .get_client_for(&prod, "key", "secret")
+            .await
+            .unwrap();
+        let prod_token = cached_token_for(&manager, &prod, "key");
+
+        let key = manager.cache_key(&staging, ClientContext::new("key"));
+        assert!(manager.invalidate_key(&key).await);
+        assert_eq!(cached_token_for(&manager, &staging, "key"), None);
+        assert_eq!(cached_token_for(&manager, &prod, "key"), prod_token);
+
+        manager
+            .get_client_for(&staging, "key", "secret")
+            .await
+            .unwrap();
+        manager
+            .get_client_for(&prod, "key", "secret")
+            .await
+            .unwrap();
+        mock.assert_calls(3);
+        assert_eq!(manager.len().await, 2);
+    }
+
+    /// A manager with a cached client for `b`, authenticating `a` until the gate opens
+    async fn authenticating_a() -> (
+        Arc<GatedBackend>,
+        ClientManager,
+        JoinHandle<Result<Client, Error>>,
+    ) {
+        let backend = GatedBackend::new();
+        let manager = ClientManager::builder()
+            .authenticator(backend.clone())
+            .clock(Arc::new(MockClock::new(1_000)))
+            .build();
+        backend.gate.add_permits(1);
+        manager.get_client("b", "secret").await.unwrap();
+
+        let a = tokio::spawn({
+            let manager = manager.clone();
+            async move { manager.get_client("a", "secret").await }
+        });
+        while backend.in_flight() < 1 {
+            tokio::task::yield_now().await;
+        }
+
+        (backend, manager, a)
+    }
+
+    #[tokio::test]
+    async fn cached_clients_dont_wait_for_other_authentications() {
+        let (backend, manager, a) = authenticating_a().await;
+
+        let b = tokio::time::timeout(Duration::from_secs(1), manager.get_client("b", "secret"));
+        b.await
+            .expect("waited for the authentication of a")
+            .unwrap();
+        assert_eq!(backend.in_flight(), 1);
+
+        backend.gate.add_permits(1);
+        a.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn detailed_cache_hits_dont_wait_for_other_authentications() {
+        let (backend, manager, a) = authenticating_a().await;
+
+        let b = tokio::time::timeout(
+            Duration::from_secs(1),
+            manager.get_client_detailed("b", "secret"),
+        );
+        let (_, outcome) = b
+            .await
+            .expect("waited for the authentication of a")
+            .unwrap();
+        assert_eq!(outcome, CacheOutcome::Hit);
+        assert_eq!(backend.in_flight(), 1);
+
+        backend.gate.add_permits(1);
+        a.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn clients_inside_the_refresh_skew_are_refreshed() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        manager.get_client("key", "secret").await.unwrap();
+
+        // 30 seconds before the expiration, within the default skew of 60
+        clock.advance(3_570);
+        let (_, outcome) = manager.get_client_detailed("key", "secret").await.unwrap();
+
+        assert_eq!(outcome, CacheOutcome::Refreshed);
+        mock.assert_calls(2);
+        assert_eq!(manager.issued_at("key"), Some(4_570));
+    }
+
+    #[tokio::test]
+    async fn clients_go_stale_and_expire_exactly_on_time() {
+        // Stale at 3540 seconds with the default skew of 60, expired at 3600
+        let after = |secs| async move {
+            let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+            let manager = manager(&mock, &clock);
+            manager.get_client("key", "secret").await.unwrap();
+            clock.advance(secs);
+
+            let expired = manager.stats().await.expired;
+            let (_, outcome) = manager.get_client_detailed("key", "secret").await.unwrap();
+            (outcome, expired)
+        };
+
+        assert_eq!(after(3_539).await, (CacheOutcome::Hit, 0));
+        assert_eq!(after(3_540).await, (CacheOutcome::Refreshed, 0));
+        assert_eq!(after(3_599).await, (CacheOutcome::Refreshed, 0));
+        assert_eq!(after(3_600).await, (CacheOutcome::Refreshed, 1));
+    }
+
+    /// Without a refresh skew, so that clients are used right up to their expiration
+    fn unskewed_manager(mock: &MockAuthenticator, clock: &MockClock) -> ClientManager {
+        ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(clock.clone()))
+            .config(ManagerConfig {
+                retry: RetryPolicy::none(),
+                refresh_skew: 0,
+                ..ManagerConfig::default()
+            })
+            .build()
+    }
+
+    #[tokio::test]
+    async fn advancing_past_the_expiration_reauthenticates() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = unskewed_manager(&mock, &clock);
+        manager.get_client("key", "secret").await.unwrap();
+
+        clock.advance(3_601);
+        manager.get_client("key", "secret").await.unwrap();
+
+        mock.assert_calls(2);
+        assert_eq!(manager.issued_at("key"), Some(4_601));
+    }
+
+    #[tokio::test]
+    async fn one_second_before_the_expiration_nothing_is_reauthenticated() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = unskewed_manager(&mock, &clock);
+        manager.get_client("key", "secret").await.unwrap();
+
+        clock.advance(3_599);
+        let (_, outcome) = manager.get_client_detailed("key", "secret").await.unwrap();
+
+        assert_eq!(outcome, CacheOutcome::Hit);
+        mock.assert_calls(1);
+        assert_eq!(manager.issued_at("key"), Some(1_000));
+    }
+
+    #[tokio::test]
+    async fn evict_expired_keeps_fresh_clients_and_the_stale_grace() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(clock.clone()))
+            .config(ManagerConfig {
+                serve_stale_for: Some(300),
+                ..ManagerConfig::default()
+            })
+            .build();
+        mock.push_token("a", 600).push_token("b", 800);
+        for api_key in ["a", "b", "c"] {
+            manager.get_client(api_key, "secret").await.unwrap();
+        }
+        assert_eq!(manager.evict_expired().await, 0);
+
+        // `a` expired 400 seconds ago, `b` only 200 seconds ago and may still be served
+        clock.advance(1_000);
+        assert_eq!(manager.evict_expired().await, 1);
+        assert_eq!(manager.len().await, 2);
+        assert_eq!(cached_token(&manager, "a"), None);
+        assert_eq!(cached_token(&manager, "b").as_deref(), Some("b"));
+
+        clock.advance(100);
+        assert_eq!(manager.evict_expired().await, 1);
+        assert_eq!(manager.evict_expired().await, 0);
+        assert!(manager.is_cached("c"));
+        assert_eq!(manager.len().await, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expired_clients_are_swept_without_a_caller() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(clock.clone()))
+            .config(ManagerConfig {
+                eviction_interval: Some(Duration::from_secs(60)),
+                ..ManagerConfig::default()
+            })
+            .build();
+        mock.push_token("short", 600);
+        manager.get_client("short", "secret").await.unwrap();
+        manager.get_client("long", "secret").await.unwrap();
+
+        clock.advance(601);
+        tokio::time::sleep(Duration::from_secs(61)).await;
+
+        assert_eq!(manager.len().await, 1);
+        assert_eq!(cached_token(&manager, "short"), None);
+        assert!(manager.is_cached("long"));
+        mock.assert_calls(2);
+    }
+
+    #[tokio::test]
+    async fn invalidate_and_force_refresh_authenticate_once_more() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        manager.get_client("key", "secret").await.unwrap();
+
+        assert!(manager.invalidate("key").await);
+        assert!(!manager.invalidate("key").await);
+        manager.get_client("key", "secret").await.unwrap();
+        manager.get_client("key", "secret").await.unwrap();
+        mock.assert_calls(2);
+
+        manager.force_refresh("key", "secret").await.unwrap();
+        manager.get_client("key", "secret").await.unwrap();
+        mock.assert_calls(3);
+        assert_eq!(cached_token(&manager, "key").as_deref(), Some("mock:key:3"));
+    }
+
+    #[tokio::test]
+    async fn refreshes_use_the_secret_currently_provided() {
+        let (backend, clock) = (RotatingBackend::new("old"), MockClock::new(1_000));
+        let manager = rotating_manager(&backend, &clock);
+        let provider = crate::StaticCredentials::new("key", "old");
+        manager.register_credentials("service", Arc::new(provider.clone()));
+        manager.get_client_by_name("service").await.unwrap();
+        assert_eq!(cached_token(&manager, "key").as_deref(), Some("old:1"));
+
+        // The secret changes at the source while the token is still valid
+        backend.rotate("new");
+        provider.store("key", &ApiSecret::new("new")).await.unwrap();
+        manager.get_client_by_name("service").await.unwrap();
+        assert_eq!(backend.calls(), 1);
+
+        clock.advance(3_600);
+        manager.get_client_by_name("service").await.unwrap();
+        assert_eq!(backend.calls(), 2);
+        assert_eq!(cached_token(&manager, "key").as_deref(), Some("new:2"));
+    }
+
+    #[tokio::test]
+    async fn issued_at_is_the_time_of_the_authentication() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        let manager = manager(&mock, &clock);
+        assert_eq!(manager.issued_at("key"), None);
+
+        clock.set(5_000);
+        manager.get_client("key", "secret").await.unwrap();
+        assert_eq!(manager.issued_at("key"), Some(5_000));
+
+        // Served from the cache, so still the time it was obtained
+        clock.set(5_100);
+        manager.get_client("key", "secret").await.unwrap();
+        assert_eq!(manager.issued_at("key"), Some(5_000));
+
+        clock.set(7_000);
+        manager.force_refresh("key", "secret").await.unwrap();
+        assert_eq!(manager.issued_at("key"), Some(7_000));
+        mock.assert_calls(2);
+    }
+
+    #[tokio::test]
+    async fn warm_up_caches_clients_and_reports_every_key() {
+        let (backend, clock) = (GatedBackend::new(), MockClock::new(1_000));
+        let manager = ClientManager::builder()
+            .authenticator(backend.clone())
+            .clock(Arc::new(clock.clone()))
+            .config(ManagerConfig {
+                retry: RetryPolicy::none(),
+                ..ManagerConfig::default()
+            })
+            .build();
+        backend.gate.add_permits(3);
+        backend.rejected.lock().unwrap().push("bad".to_string());
+
+        let results = manager
+            .warm_up(["a", "bad", "b"].map(|api_key| (api_key.into(), "secret".into())))
+            .await;
+
+        let outcomes: Vec<_> = results
+            .iter()
+            .map(|(api_key, res)| (api_key.as_str(), res.is_ok()))
+            .collect();
+        assert_eq!(outcomes, [("a", true), ("bad", false), ("b", true)]);
+        assert_eq!(
+            results[1].1.as_ref().unwrap_err().kind(),
+            ErrorKind::Rejected
+        );
+        assert!(manager.is_cached("a") && manager.is_cached("b"));
+        assert!(!manager.is_cached("bad"));
+        assert_eq!(manager.len().await, 2);
+    }
+
+    #[tokio::test]
+    async fn refresh_tokens_replace_full_authentications() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        mock.issue_refresh_tokens(true);
+        let manager = manager(&mock, &clock);
+        manager.get_client("key", "secret").await.unwrap();
+
+        for refresh in 1..=2 {
+            clock.advance(3_600);
+            manager.get_client("key", "secret").await.unwrap();
+
+            assert_eq!((mock.calls(), mock.refreshes()), (1, refresh));
+            let token = format!("mock:key:refresh:{refresh}");
+            assert_eq!(cached_token(&manager, "key"), Some(token));
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_refreshes_fall_back_to_authenticating() {
+        let (mock, clock) = (MockAuthenticator::default(), MockClock::new(1_000));
+        mock.issue_refresh_tokens(true);
+        let manager = manager(&mock, &clock);
+        manager.get_client("key", "secret").await.unwrap();
+
+        mock.set_refresh_failing(true);
+        clock.advance(3_600);
+        manager.get_client("key", "secret").await.unwrap();
+
+        assert_eq!((mock.calls(), mock.refreshes()), (2, 1));
+        assert_eq!(cached_token(&manager, "key").as_deref(), Some("mock:key:2"));
+    }
+}
@@ -0,0 +1,146 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Why an authentication failed, as far as metrics are concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FailureKind {
+    /// The backend refused the credentials
+    Rejected,
+    /// The backend couldn't be reached or had a problem of its own, retries included
+    Transient,
+}
+
+/// Hooks called by a [`ClientManager`](crate::ClientManager) at the interesting points of a refresh
+///
+/// All methods default to doing nothing, so implementations only need to override what they record.
+/// They are never called while the manager holds a lock, but still shouldn't block for long
+/// as they are called inline by whoever requested the client.
+pub trait Metrics: Send + Sync {
+    /// A fresh client was served from the cache
+    fn on_cache_hit(&self) {}
+
+    /// No fresh client was cached, the caller either authenticates or waits for an authentication
+    fn on_cache_miss(&self) {}
+
+    /// An authentication succeeded after `duration`, including any retries
+    fn on_auth_success(&self, _duration: Duration) {}
+
+    /// An authentication failed after `duration`, including any retries
+    fn on_auth_failure(&self, _duration: Duration, _kind: FailureKind) {}
+
+    /// A refresh failed and the stale cached client was served instead, see
+    /// [`ManagerConfig::serve_stale_for`](crate::ManagerConfig::serve_stale_for)
+    fn on_stale_served(&self) {}
+
+    /// A client was evicted, either to stay within the capacity or because it expired
+    fn on_eviction(&self) {}
+
+    /// The backend returned a token expiring after `expires_in` seconds, longer than
+    /// [`ManagerConfig::max_lifetime`](crate::ManagerConfig::max_lifetime)
+    fn on_lifetime_clamped(&self, _expires_in: i64) {}
+}
+
+/// Records nothing
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+/// Counts every hook call, e.g. to be exported periodically or checked in tests
+#[derive(Debug, Default)]
+pub struct AtomicMetrics {
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    auth_successes: AtomicU64,
+    auth_failures: AtomicU64,
+    rejections: AtomicU64,
+    auth_nanos: AtomicU64,
+    stale_served: AtomicU64,
+    evictions: AtomicU64,
+    lifetimes_clamped: AtomicU64,
+}
+
+impl AtomicMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cache_hits(&self) -> u64 {
+        self.cache_hits.load(Ordering::Relaxed)
+    }
+
+    pub fn cache_misses(&self) -> u64 {
+        self.cache_misses.load(Ordering::Relaxed)
+    }
+
+    pub fn auth_successes(&self) -> u64 {
+        self.auth_successes.load(Ordering::Relaxed)
+    }
+
+    /// All failed authentications, including [rejections](Self::rejections)
+    pub fn auth_failures(&self) -> u64 {
+        self.auth_failures.load(Ordering::Relaxed)
+    }
+
+    /// Failed authentications of kind [`FailureKind::Rejected`]
+    pub fn rejections(&self) -> u64 {
+        self.rejections.load(Ordering::Relaxed)
+    }
+
+    /// Total time spent authenticating, successfully or not
+    pub fn auth_time(&self) -> Duration {
+        Duration::from_nanos(self.auth_nanos.load(Ordering::Relaxed))
+    }
+
+    pub fn stale_served(&self) -> u64 {
+        self.stale_served.load(Ordering::Relaxed)
+    }
+
+    pub fn evictions(&self) -> u64 {
+        self.evictions.load(Ordering::Relaxed)
+    }
+
+    pub fn lifetimes_clamped(&self) -> u64 {
+        self.lifetimes_clamped.load(Ordering::Relaxed)
+    }
+
+    fn record_auth(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.auth_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+}
+
+impl Metrics for AtomicMetrics {
+    fn on_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_auth_success(&self, duration: Duration) {
+        self.auth_successes.fetch_add(1, Ordering::Relaxed);
+        self.record_auth(duration);
+    }
+
+    fn on_auth_failure(&self, duration: Duration, kind: FailureKind) {
+        self.auth_failures.fetch_add(1, Ordering::Relaxed);
+        if kind == FailureKind::Rejected {
+            self.rejections.fetch_add(1, Ordering::Relaxed);
+        }
+        self.record_auth(duration);
+    }
+
+    fn on_stale_served(&self) {
+        self.stale_served.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_eviction(&self) {
+        self.evictions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_lifetime_clamped(&self, _expires_in: i64) {
+        self.lifetimes_clamped.fetch_add(1, Ordering::Relaxed);
+    }
+}
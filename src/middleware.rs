@@ -0,0 +1,168 @@
+//! Authorization of `reqwest-middleware` clients, available with the `middleware` feature
+
+use async_trait::async_trait;
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next};
+use task_local_extensions::Extensions;
+
+use crate::{ApiKey, ApiSecret, ClientManager, Error};
+
+/// Adds the authentication headers of the token for a single api key to every request of a
+/// [`ClientWithMiddleware`](reqwest_middleware::ClientWithMiddleware)
+///
+/// The token comes from the manager's cache and is refreshed once it goes stale, with concurrent
+/// requests waiting for a single authentication. A request answered as unauthorized is sent again
+/// after a forced refresh, as configured by the manager's [`ReplayPolicy`](crate::ReplayPolicy)
+/// and under the same conditions as by an [`AuthedClient`](crate::AuthedClient). Failures to
+/// authenticate are reported as [`reqwest_middleware::Error::Middleware`] wrapping an [`Error`].
+///
+/// The client the middleware is attached to sends the requests, so its connection pool outlives
+/// every token.
+#[derive(Debug, Clone)]
+pub struct AuthMiddleware {
+    manager: ClientManager,
+    api_key: ApiKey,
+    api_secret: ApiSecret,
+    replay_non_idempotent: bool,
+}
+
+impl AuthMiddleware {
+    pub fn new(
+        manager: ClientManager,
+        api_key: impl Into<ApiKey>,
+        api_secret: impl Into<ApiSecret>,
+    ) -> Self {
+        Self {
+            manager,
+            api_key: api_key.into(),
+            api_secret: api_secret.into(),
+            replay_non_idempotent: false,
+        }
+    }
+
+    /// Whether requests like `POST` may be replayed as well, which could apply them twice
+    pub fn replay_non_idempotent(mut self, replay: bool) -> Self {
+        self.replay_non_idempotent = replay;
+        self
+    }
+
+    async fn authorize(&self, request: &mut Request, force: bool) -> Result<(), Error> {
+        let headers = self
+            .manager
+            .authorization(&self.api_key, &self.api_secret, force)
+            .await?;
+        request.headers_mut().extend(headers);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Middleware for AuthMiddleware {
+    async fn handle(
+        &self,
+        mut request: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let policy = &self.manager.config().replay;
+        let replayable = self.replay_non_idempotent || request.method().is_idempotent();
+
+        let mut force = false;
+        let mut replays = 0;
+        loop {
+            self.authorize(&mut request, force)
+                .await
+                .map_err(reqwest_middleware::Error::middleware)?;
+            let replay = if replayable && replays < policy.max_replays {
+                request.try_clone()
+            } else {
+                None
+            };
+
+            let response = next.clone().run(request, extensions).await?;
+            if !policy.statuses.contains(&response.status()) {
+                return Ok(response);
+            }
+            let Some(replay) = replay else {
+                return Ok(response);
+            };
+
+            event!(DEBUG, status = %response.status(), "Replaying a rejected request");
+            force = true;
+            request = replay;
+            replays += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use reqwest::StatusCode;
+    use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+    use super::*;
+    use crate::test_server::TestServer;
+    use crate::MockAuthenticator;
+
+    fn client(mock: &MockAuthenticator) -> ClientWithMiddleware {
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .build();
+
+        ClientBuilder::new(reqwest::Client::new())
+            .with(AuthMiddleware::new(manager, "key", "secret"))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn replays_after_unauthorized() {
+        let server = TestServer::start([401]).await;
+        let mock = MockAuthenticator::default();
+        let client = client(&mock);
+
+        let response = client.get(&server.url).send().await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            server.authorizations(),
+            ["Bearer mock:key:1", "Bearer mock:key:2"]
+        );
+        mock.assert_calls(2);
+    }
+
+    #[tokio::test]
+    async fn stops_after_one_refresh() {
+        let server = TestServer::start([401, 403, 401]).await;
+        let mock = MockAuthenticator::default();
+
+        let response = client(&mock).get(&server.url).send().await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            server.authorizations(),
+            ["Bearer mock:key:1", "Bearer mock:key:2"]
+        );
+        mock.assert_calls(2);
+    }
+
+    #[tokio::test]
+    async fn authentication_failures_are_middleware_errors() {
+        let server = TestServer::start([]).await;
+        let mock = MockAuthenticator::default();
+        mock.set_failing(true);
+
+        let err = client(&mock).get(&server.url).send().await.unwrap_err();
+
+        let reqwest_middleware::Error::Middleware(err) = err else {
+            panic!("Expected a middleware error, got {err:?}");
+        };
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::AuthenticationFailed { .. })
+        ));
+        assert!(server.authorizations().is_empty());
+    }
+}
@@ -0,0 +1,267 @@
+//! Test doubles, available with the `testing` feature
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::{ApiSecret, AuthConfig, AuthOutput, Authenticator};
+
+/// An authenticator for tests that counts its calls and can be told to fail
+///
+/// Responses enqueued with the `push_*` methods are returned first, in order.
+/// Once the queue is empty every successful call mints a distinct token.
+/// Clones share their state.
+#[derive(Debug, Clone)]
+pub struct MockAuthenticator {
+    inner: Arc<MockState>,
+}
+
+#[derive(Debug)]
+struct MockState {
+    expires_in: i64,
+    calls: AtomicUsize,
+    failing: AtomicBool,
+    transient_failures: AtomicUsize,
+    script: Mutex<VecDeque<anyhow::Result<AuthOutput>>>,
+    refresh_tokens: AtomicBool,
+    refreshes: AtomicUsize,
+    refresh_failing: AtomicBool,
+    delay: Mutex<Duration>,
+}
+
+/// The error returned by a failing [`MockAuthenticator`]
+#[derive(Debug, thiserror::Error)]
+#[error("Mock authentication failure (transient: {transient})")]
+struct MockError {
+    transient: bool,
+    retry_after: Option<Duration>,
+}
+
+impl MockAuthenticator {
+    /// Creates a mock issuing tokens that expire after `expires_in` seconds
+    pub fn new(expires_in: i64) -> Self {
+        Self {
+            inner: Arc::new(MockState {
+                expires_in,
+                calls: AtomicUsize::new(0),
+                failing: AtomicBool::new(false),
+                transient_failures: AtomicUsize::new(0),
+                script: Mutex::new(VecDeque::new()),
+                refresh_tokens: AtomicBool::new(false),
+                refreshes: AtomicUsize::new(0),
+                refresh_failing: AtomicBool::new(false),
+                delay: Mutex::new(Duration::ZERO),
+            }),
+        }
+    }
+
+    /// How many times `authenticate` has been called, including failed calls
+    pub fn calls(&self) -> usize {
+        self.inner.calls.load(Ordering::SeqCst)
+    }
+
+    /// Panics unless `authenticate` has been called exactly `expected` times
+    #[track_caller]
+    pub fn assert_calls(&self, expected: usize) {
+        let calls = self.calls();
+        assert_eq!(
+            calls, expected,
+            "expected {expected} authentications, got {calls}"
+        );
+    }
+
+    /// How many times `refresh` has been called, including failed calls
+    pub fn refreshes(&self) -> usize {
+        self.inner.refreshes.load(Ordering::SeqCst)
+    }
+
+    /// Makes minted tokens come with a refresh token, which `refresh` exchanges for a new token
+    /// without counting as a call to `authenticate`
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), existing_code_challenge::Error> {
+    /// use std::sync::Arc;
+    ///
+    /// use existing_code_challenge::{ClientManager, MockAuthenticator, MockClock};
+    ///
+    /// let clock = MockClock::new(0);
+    /// let mock = MockAuthenticator::new(3600);
+    /// mock.issue_refresh_tokens(true);
+    /// let manager = ClientManager::builder()
+    ///     .authenticator(Arc::new(mock.clone()))
+    ///     .clock(Arc::new(clock.clone()))
+    ///     .build();
+    ///
+    /// manager.get_client("key", "secret").await?;
+    /// clock.advance(3600);
+    /// manager.get_client("key", "secret").await?;
+    /// assert_eq!((mock.calls(), mock.refreshes()), (1, 1));
+    ///
+    /// // A refused refresh token falls back to authenticating with the secret
+    /// mock.set_refresh_failing(true);
+    /// clock.advance(3600);
+    /// manager.get_client("key", "secret").await?;
+    /// assert_eq!((mock.calls(), mock.refreshes()), (2, 2));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn issue_refresh_tokens(&self, issue: bool) {
+        self.inner.refresh_tokens.store(issue, Ordering::SeqCst);
+    }
+
+    /// Makes subsequent refreshes fail as if the refresh token was revoked (or succeed again)
+    pub fn set_refresh_failing(&self, failing: bool) {
+        self.inner.refresh_failing.store(failing, Ordering::SeqCst);
+    }
+
+    /// Makes subsequent calls fail with a non-retryable error (or succeed again)
+    pub fn set_failing(&self, failing: bool) {
+        self.inner.failing.store(failing, Ordering::SeqCst);
+    }
+
+    /// Makes every call to `authenticate` starting from now take `delay` before responding, as
+    /// measured by Tokio's clock so that tests with paused time don't actually wait
+    ///
+    /// Scripted responses are taken in the order of the calls, not of their completion, so a
+    /// slow call can be made to finish after a faster one started later.
+    pub fn set_delay(&self, delay: Duration) {
+        *self.inner.delay.lock().unwrap() = delay;
+    }
+
+    /// Makes the next `count` calls fail with a retryable error
+    pub fn fail_next(&self, count: usize) {
+        self.inner.transient_failures.store(count, Ordering::SeqCst);
+    }
+
+    /// Enqueues a successful response with the given token
+    pub fn push_token(&self, access_token: impl Into<String>, expires_in: i64) -> &Self {
+        self.push_output(AuthOutput::new(access_token, expires_in))
+    }
+
+    /// Enqueues a successful response, e.g. one carrying a refresh token
+    pub fn push_output(&self, output: AuthOutput) -> &Self {
+        self.inner.script.lock().unwrap().push_back(Ok(output));
+        self
+    }
+
+    /// Enqueues a failure, which is never retried
+    pub fn push_error(&self, err: impl Into<anyhow::Error>) -> &Self {
+        self.inner.script.lock().unwrap().push_back(Err(err.into()));
+        self
+    }
+
+    /// Enqueues a credentials rejection, which is never retried
+    pub fn push_rejection(&self) -> &Self {
+        self.push_error(MockError {
+            transient: false,
+            retry_after: None,
+        })
+    }
+
+    /// Enqueues a throttled request, retryable after waiting for `retry_after`
+    pub fn push_rate_limited(&self, retry_after: Duration) -> &Self {
+        self.push_error(MockError {
+            transient: true,
+            retry_after: Some(retry_after),
+        })
+    }
+
+    /// Enqueues a failure the manager considers worth retrying
+    pub fn push_transient_error(&self) -> &Self {
+        self.push_error(MockError {
+            transient: true,
+            retry_after: None,
+        })
+    }
+
+    fn mint(&self, access_token: String) -> AuthOutput {
+        let output = AuthOutput::new(&access_token, self.inner.expires_in);
+        if !self.inner.refresh_tokens.load(Ordering::SeqCst) {
+            return output;
+        }
+
+        output.with_refresh_token(format!("{access_token}:refresh-token"))
+    }
+}
+
+impl Default for MockAuthenticator {
+    fn default() -> Self {
+        Self::new(3600)
+    }
+}
+
+#[async_trait]
+impl Authenticator for MockAuthenticator {
+    async fn authenticate(
+        &self,
+        _config: &AuthConfig,
+        api_key: &str,
+        _api_secret: &ApiSecret,
+    ) -> anyhow::Result<AuthOutput> {
+        let call = self.inner.calls.fetch_add(1, Ordering::SeqCst) + 1;
+        let scripted = self.inner.script.lock().unwrap().pop_front();
+        let delay = *self.inner.delay.lock().unwrap();
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+
+        if let Some(res) = scripted {
+            return res;
+        }
+
+        if self.inner.failing.load(Ordering::SeqCst) {
+            return Err(MockError {
+                transient: false,
+                retry_after: None,
+            }
+            .into());
+        }
+
+        let transient = self
+            .inner
+            .transient_failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if transient {
+            return Err(MockError {
+                transient: true,
+                retry_after: None,
+            }
+            .into());
+        }
+
+        Ok(self.mint(format!("mock:{api_key}:{call}")))
+    }
+
+    async fn refresh(
+        &self,
+        _config: &AuthConfig,
+        api_key: &str,
+        _refresh_token: &str,
+    ) -> anyhow::Result<AuthOutput> {
+        let refresh = self.inner.refreshes.fetch_add(1, Ordering::SeqCst) + 1;
+
+        if self.inner.refresh_failing.load(Ordering::SeqCst) {
+            return Err(MockError {
+                transient: false,
+                retry_after: None,
+            }
+            .into());
+        }
+
+        Ok(self.mint(format!("mock:{api_key}:refresh:{refresh}")))
+    }
+
+    fn is_retryable(&self, err: &anyhow::Error) -> bool {
+        err.downcast_ref::<MockError>()
+            .is_some_and(|err| err.transient)
+    }
+
+    fn retry_after(&self, err: &anyhow::Error) -> Option<Duration> {
+        err.downcast_ref::<MockError>()?.retry_after
+    }
+}
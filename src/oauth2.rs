@@ -0,0 +1,171 @@
+//! Authentication through a standard OAuth2 token endpoint, available with the `oauth2` feature
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, StatusCode, Url};
+use serde::Deserialize;
+
+use crate::auth::is_transient;
+use crate::{
+    parse_retry_after, ApiSecret, AuthConfig, AuthOutput, Authenticator, Clock, ErrorKind,
+    SystemClock,
+};
+
+/// Obtains tokens with the OAuth2 client credentials grant, using the api key as the client id
+/// and the secret as the client secret
+///
+/// The credentials are sent with HTTP basic authentication to the token URL, the [`AuthConfig`]
+/// is ignored. Only `Bearer` tokens are accepted, which is assumed if the response has no `token_type`.
+/// Scoped tokens are requested with the `scope` parameter.
+#[derive(Debug, Clone)]
+pub struct OAuth2Authenticator {
+    token_url: Url,
+    client: Client,
+}
+
+/// A failure specific to the token endpoint, network errors are reported as `reqwest::Error`
+#[derive(Debug, thiserror::Error)]
+pub enum OAuth2Error {
+    /// The endpoint refused the request, e.g. with `401` for unknown credentials
+    #[error("Token endpoint responded with {status}: {body}")]
+    Status {
+        status: StatusCode,
+        body: String,
+        /// Parsed from the `Retry-After` header, if any
+        retry_after: Option<Duration>,
+    },
+
+    #[error("Invalid token response: {0}")]
+    InvalidResponse(#[source] serde_json::Error),
+
+    #[error("Unsupported token type {0}, only Bearer tokens can be used")]
+    UnsupportedTokenType(String),
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    token_type: Option<String>,
+    expires_in: i64,
+    /// Space separated, only sent if the granted scopes differ from the requested ones
+    scope: Option<String>,
+}
+
+impl OAuth2Authenticator {
+    pub fn new(token_url: Url) -> Self {
+        Self::with_client(token_url, Client::new())
+    }
+
+    /// Sends the token requests through `client`, e.g. to configure timeouts or a proxy
+    pub fn with_client(token_url: Url, client: Client) -> Self {
+        Self { token_url, client }
+    }
+
+    pub fn token_url(&self) -> &Url {
+        &self.token_url
+    }
+
+    async fn request_token(
+        &self,
+        api_key: &str,
+        api_secret: &ApiSecret,
+        scopes: &[String],
+    ) -> anyhow::Result<AuthOutput> {
+        let mut form = vec![("grant_type", "client_credentials".to_string())];
+        if !scopes.is_empty() {
+            form.push(("scope", scopes.join(" ")));
+        }
+
+        let response = self
+            .client
+            .post(self.token_url.clone())
+            .basic_auth(api_key, Some(api_secret.expose_secret()))
+            .form(&form)
+            .send()
+            .await?;
+
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| parse_retry_after(value, SystemClock.now()));
+        let body = response.bytes().await?;
+        if !status.is_success() {
+            let body = String::from_utf8_lossy(&body).into_owned();
+            return Err(OAuth2Error::Status {
+                status,
+                body,
+                retry_after,
+            }
+            .into());
+        }
+
+        let token: TokenResponse =
+            serde_json::from_slice(&body).map_err(OAuth2Error::InvalidResponse)?;
+        if let Some(token_type) = token.token_type {
+            if !token_type.eq_ignore_ascii_case("bearer") {
+                return Err(OAuth2Error::UnsupportedTokenType(token_type).into());
+            }
+        }
+
+        let mut output = AuthOutput::new(token.access_token, token.expires_in);
+        if let Some(scope) = token.scope {
+            output = output.with_scope(scope.split_whitespace());
+        }
+
+        Ok(output)
+    }
+}
+
+#[async_trait]
+impl Authenticator for OAuth2Authenticator {
+    async fn authenticate(
+        &self,
+        _config: &AuthConfig,
+        api_key: &str,
+        api_secret: &ApiSecret,
+    ) -> anyhow::Result<AuthOutput> {
+        self.request_token(api_key, api_secret, &[]).await
+    }
+
+    async fn authenticate_scoped(
+        &self,
+        _config: &AuthConfig,
+        api_key: &str,
+        api_secret: &ApiSecret,
+        scopes: &[String],
+    ) -> anyhow::Result<AuthOutput> {
+        self.request_token(api_key, api_secret, scopes).await
+    }
+
+    /// Network errors, server errors and throttling are retried, everything else is a rejection
+    fn is_retryable(&self, err: &anyhow::Error) -> bool {
+        match err.downcast_ref::<OAuth2Error>() {
+            Some(OAuth2Error::Status { status, .. }) => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            Some(_) => false,
+            None => is_transient(err),
+        }
+    }
+
+    /// Responses that can't be used are invalid, see [`is_retryable`](Self::is_retryable) for the rest
+    fn classify(&self, err: &anyhow::Error) -> ErrorKind {
+        match err.downcast_ref::<OAuth2Error>() {
+            Some(OAuth2Error::InvalidResponse(_) | OAuth2Error::UnsupportedTokenType(_)) => {
+                ErrorKind::Invalid
+            }
+            _ if self.is_retryable(err) => ErrorKind::Transient,
+            _ => ErrorKind::Rejected,
+        }
+    }
+
+    fn retry_after(&self, err: &anyhow::Error) -> Option<Duration> {
+        match err.downcast_ref::<OAuth2Error>()? {
+            OAuth2Error::Status { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
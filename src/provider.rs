@@ -0,0 +1,104 @@
+//! Sources of credentials, resolved by name when a client has to be authenticated
+
+use std::env;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+
+use crate::{ApiKey, ApiSecret};
+
+const API_KEY_VAR: &str = "MYLIB_API_KEY";
+const API_SECRET_VAR: &str = "MYLIB_API_SECRET";
+
+/// Supplies the credentials registered under a name with
+/// [`ClientManager::register_credentials`](crate::ClientManager::register_credentials)
+///
+/// Only asked when the client has to be authenticated, so a provider backed by a secrets
+/// manager is called about once per token lifetime, and may return a rotated secret every time.
+#[async_trait]
+pub trait CredentialsProvider: Send + Sync {
+    async fn credentials(&self) -> anyhow::Result<(ApiKey, ApiSecret)>;
+
+    /// Stores the secret `api_key` was rotated to by
+    /// [`ClientManager::rotate_credentials`](crate::ClientManager::rotate_credentials), so that
+    /// it's provided from now on
+    ///
+    /// The default does nothing, for providers whose source is updated by other means.
+    async fn store(&self, _api_key: &str, _api_secret: &ApiSecret) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Always provides the same credentials, or the secret they were last rotated to
+///
+/// Clones share the secret.
+#[derive(Debug, Clone)]
+pub struct StaticCredentials {
+    api_key: ApiKey,
+    api_secret: Arc<RwLock<ApiSecret>>,
+}
+
+impl StaticCredentials {
+    pub fn new(api_key: impl Into<ApiKey>, api_secret: impl Into<ApiSecret>) -> Self {
+        Self {
+            api_key: api_key.into(),
+            api_secret: Arc::new(RwLock::new(api_secret.into())),
+        }
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for StaticCredentials {
+    async fn credentials(&self) -> anyhow::Result<(ApiKey, ApiSecret)> {
+        let api_secret = self.api_secret.read().unwrap().clone();
+
+        Ok((self.api_key.clone(), api_secret))
+    }
+
+    async fn store(&self, api_key: &str, api_secret: &ApiSecret) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            api_key == self.api_key.as_str(),
+            "Credentials are for another api key"
+        );
+        *self.api_secret.write().unwrap() = api_secret.clone();
+
+        Ok(())
+    }
+}
+
+/// Reads the credentials from environment variables whenever they're needed,
+/// `MYLIB_API_KEY` and `MYLIB_API_SECRET` by default
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvCredentials {
+    api_key_var: String,
+    api_secret_var: String,
+}
+
+impl EnvCredentials {
+    pub fn new(api_key_var: impl Into<String>, api_secret_var: impl Into<String>) -> Self {
+        Self {
+            api_key_var: api_key_var.into(),
+            api_secret_var: api_secret_var.into(),
+        }
+    }
+}
+
+impl Default for EnvCredentials {
+    fn default() -> Self {
+        Self::new(API_KEY_VAR, API_SECRET_VAR)
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for EnvCredentials {
+    async fn credentials(&self) -> anyhow::Result<(ApiKey, ApiSecret)> {
+        let var = |name: &str| {
+            env::var(name).map_err(|err| anyhow::anyhow!("Failed to read {name}: {err}"))
+        };
+
+        Ok((
+            ApiKey::new(var(&self.api_key_var)?),
+            ApiSecret::new(var(&self.api_secret_var)?),
+        ))
+    }
+}
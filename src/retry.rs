@@ -0,0 +1,251 @@
+use std::time::Duration;
+
+use chrono::DateTime;
+use rand::Rng;
+use reqwest::header::HeaderValue;
+
+/// How failed authentications are retried
+///
+/// Only errors the [`Authenticator`](crate::Authenticator) classifies as retryable are retried.
+/// The delay before each retry grows from `base_delay` by `multiplier` per attempt up to `max_delay`.
+/// With `jitter` enabled a random delay between zero and that value is used instead,
+/// so that many callers don't retry in lockstep.
+///
+/// If the backend asks to wait longer, e.g. with a `Retry-After` header reported through
+/// [`Authenticator::retry_after`](crate::Authenticator::retry_after), the retry waits that long instead,
+/// but at most `max_retry_after`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub max_attempts: u32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::secs"))]
+    pub base_delay: Duration,
+    pub multiplier: f64,
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::secs"))]
+    pub max_delay: Duration,
+    pub jitter: bool,
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::secs"))]
+    pub max_retry_after: Duration,
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// The delay before the retry following attempt number `attempt` (starting at 1)
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let backoff = self.base_delay.as_secs_f64() * self.multiplier.max(1.0).powi(exponent);
+        let backoff = Duration::from_secs_f64(backoff.min(self.max_delay.as_secs_f64()));
+
+        if self.jitter {
+            backoff.mul_f64(rand::thread_rng().gen())
+        } else {
+            backoff
+        }
+    }
+
+    /// Like [`delay`](Self::delay), but at least `retry_after` if the backend asked for it
+    pub(crate) fn delay_after(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let delay = self.delay(attempt);
+
+        match retry_after {
+            Some(retry_after) => delay.max(retry_after.min(self.max_retry_after)),
+            None => delay,
+        }
+    }
+}
+
+/// Parses a `Retry-After` header, given either in seconds or as an HTTP date
+///
+/// A date is compared to `now`, a unix timestamp in seconds as returned by
+/// [`Clock::now`](crate::Clock::now). A date in the past means retrying right away.
+pub fn parse_retry_after(value: &HeaderValue, now: i64) -> Option<Duration> {
+    let value = value.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(Duration::from_secs(
+        u64::try_from(at.timestamp() - now).unwrap_or_default(),
+    ))
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(5),
+            jitter: true,
+            max_retry_after: Duration::from_secs(60),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{ClientManager, Error, ErrorKind, ManagerConfig, MockAuthenticator, MockClock};
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(5),
+            jitter: false,
+            max_retry_after: Duration::from_secs(30),
+        }
+    }
+
+    #[test]
+    fn delays_grow_up_to_the_cap() {
+        let policy = policy();
+
+        let delays: Vec<_> = (1..=5)
+            .map(|attempt| policy.delay(attempt).as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 5, 5]);
+        // Large attempt numbers don't overflow
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn multiplier_below_one_keeps_the_base_delay() {
+        let policy = RetryPolicy {
+            multiplier: 0.5,
+            ..policy()
+        };
+
+        assert_eq!(policy.delay(3), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn jitter_stays_below_the_backoff() {
+        let policy = RetryPolicy {
+            jitter: true,
+            ..policy()
+        };
+
+        for _ in 0..100 {
+            assert!(policy.delay(3) <= Duration::from_secs(4));
+        }
+    }
+
+    #[test]
+    fn retry_after_extends_the_delay_up_to_its_cap() {
+        let policy = policy();
+
+        assert_eq!(policy.delay_after(1, None), Duration::from_secs(1));
+        assert_eq!(
+            policy.delay_after(1, Some(Duration::from_secs(10))),
+            Duration::from_secs(10)
+        );
+        // Never shorter than the backoff
+        assert_eq!(
+            policy.delay_after(3, Some(Duration::from_millis(10))),
+            Duration::from_secs(4)
+        );
+        assert_eq!(
+            policy.delay_after(1, Some(Duration::from_secs(600))),
+            Duration::from_secs(30)
+        );
+    }
+
+    fn manager(mock: &MockAuthenticator) -> ClientManager {
+        ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(MockClock::new(1_000)))
+            .config(ManagerConfig {
+                retry: policy(),
+                ..ManagerConfig::default()
+            })
+            .build()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn succeeds_after_two_failures() {
+        let mock = MockAuthenticator::default();
+        mock.fail_next(2);
+
+        let client = manager(&mock).get_client("key", "secret").await;
+
+        assert!(client.is_ok());
+        mock.assert_calls(3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_max_attempts() {
+        let mock = MockAuthenticator::default();
+        mock.fail_next(10);
+
+        let start = tokio::time::Instant::now();
+        let err = manager(&mock)
+            .get_client("key", "secret")
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, Error::AuthenticationFailed { .. }));
+        assert_eq!(err.kind(), ErrorKind::Transient);
+        mock.assert_calls(4);
+        assert_eq!(start.elapsed(), Duration::from_secs(1 + 2 + 4));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rejections_are_not_retried() {
+        let mock = MockAuthenticator::default();
+        mock.push_rejection();
+
+        let err = manager(&mock)
+            .get_client("key", "secret")
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::Rejected);
+        mock.assert_calls(1);
+    }
+
+    #[test]
+    fn retry_after_is_parsed_from_seconds_or_a_date() {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        let now = 784_111_777;
+
+        let parse = |value| parse_retry_after(&HeaderValue::from_static(value), now);
+
+        assert_eq!(parse("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse("Sun, 06 Nov 1994 08:50:07 GMT"),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(parse("Sun, 06 Nov 1994 08:00:00 GMT"), Some(Duration::ZERO));
+        assert_eq!(parse("soon"), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limited_requests_wait_for_retry_after() {
+        let mock = MockAuthenticator::default();
+        mock.push_rate_limited(Duration::from_secs(7));
+
+        let start = tokio::time::Instant::now();
+        let client = manager(&mock).get_client("key", "secret").await;
+
+        assert!(client.is_ok());
+        mock.assert_calls(2);
+        assert_eq!(start.elapsed(), Duration::from_secs(7));
+    }
+}
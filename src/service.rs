@@ -0,0 +1,158 @@
+//! Authorization of `tower` services, available with the `tower` feature
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tower::{Layer, Service};
+
+use crate::{ApiKey, ApiSecret, ClientManager, Error};
+
+/// Wraps services in an [`AuthService`] for a single api key
+#[derive(Debug, Clone)]
+pub struct AuthLayer {
+    manager: ClientManager,
+    api_key: ApiKey,
+    api_secret: ApiSecret,
+}
+
+impl AuthLayer {
+    pub fn new(
+        manager: ClientManager,
+        api_key: impl Into<ApiKey>,
+        api_secret: impl Into<ApiSecret>,
+    ) -> Self {
+        Self {
+            manager,
+            api_key: api_key.into(),
+            api_secret: api_secret.into(),
+        }
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = AuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuthService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Adds the authentication headers of the token for a single api key to every request before
+/// passing it on to the inner service
+///
+/// The token comes from the manager's cache and is refreshed once it goes stale, with concurrent
+/// requests waiting for a single authentication. Readiness is that of the inner service, a
+/// refresh only ever happens within the future returned by `call`.
+#[derive(Debug, Clone)]
+pub struct AuthService<S> {
+    inner: S,
+    layer: AuthLayer,
+}
+
+/// The error of an [`AuthService`]
+#[derive(Debug, thiserror::Error)]
+pub enum AuthServiceError<E> {
+    /// Obtaining the token failed, the request wasn't passed on
+    #[error(transparent)]
+    Auth(Error),
+    /// The inner service failed
+    #[error(transparent)]
+    Inner(E),
+}
+
+impl<S, B> Service<http::Request<B>> for AuthService<S>
+where
+    S: Service<http::Request<B>> + Clone + Send + 'static,
+    S::Future: Send,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = AuthServiceError<S::Error>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(AuthServiceError::Inner)
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        // The clone isn't necessarily ready, the one that was polled is taken instead
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let AuthLayer {
+            manager,
+            api_key,
+            api_secret,
+        } = self.layer.clone();
+
+        Box::pin(async move {
+            let headers = manager
+                .authorization(&api_key, &api_secret, false)
+                .await
+                .map_err(AuthServiceError::Auth)?;
+            request.headers_mut().extend(headers);
+
+            inner.call(request).await.map_err(AuthServiceError::Inner)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::future::{poll_fn, ready, Ready};
+    use std::sync::{Arc, Mutex};
+
+    use reqwest::header::{HeaderMap, AUTHORIZATION};
+
+    use super::*;
+    use crate::{MockAuthenticator, MockClock};
+
+    /// Records the headers of every request and answers right away
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<HeaderMap>>>);
+
+    impl Service<http::Request<()>> for Recorder {
+        type Response = ();
+        type Error = Infallible;
+        type Future = Ready<Result<(), Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<()>) -> Self::Future {
+            self.0.lock().unwrap().push(request.headers().clone());
+            ready(Ok(()))
+        }
+    }
+
+    async fn send(service: &mut AuthService<Recorder>) {
+        poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+        service.call(http::Request::new(())).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn requests_carry_the_token_of_the_rotated_secret() {
+        let mock = MockAuthenticator::default();
+        let manager = ClientManager::builder()
+            .authenticator(Arc::new(mock.clone()))
+            .clock(Arc::new(MockClock::new(1_000)))
+            .build();
+        let recorder = Recorder::default();
+        let mut service = AuthLayer::new(manager.clone(), "key", "old").layer(recorder.clone());
+
+        send(&mut service).await;
+        manager.rotate_credentials("key", "new").await.unwrap();
+        send(&mut service).await;
+
+        let requests = recorder.0.lock().unwrap().clone();
+        assert_eq!(requests[0][AUTHORIZATION], "Bearer mock:key:1");
+        assert_eq!(requests[1][AUTHORIZATION], "Bearer mock:key:2");
+        assert!(requests.iter().all(|headers| headers["x-api-key"] == "key"));
+        mock.assert_calls(2);
+    }
+}
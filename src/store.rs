@@ -0,0 +1,325 @@
+//! Persistence of tokens beyond the lifetime of a manager
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::AuthConfig;
+
+/// An access token as persisted by a [`TokenStore`], never including the secret it was obtained with
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "persist", derive(serde::Serialize, serde::Deserialize))]
+pub struct StoredToken {
+    pub access_token: String,
+    /// Unix timestamp at which the token was obtained
+    pub issued_at: i64,
+    /// Unix timestamp at which the token expires
+    pub expires_at: i64,
+}
+
+impl std::fmt::Debug for StoredToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StoredToken")
+            .field("access_token", &"<redacted>")
+            .field("issued_at", &self.issued_at)
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+/// Keeps tokens across restarts or shares them between processes, e.g. for a CLI that would
+/// otherwise authenticate on every run, or instances of a service sharing a Redis
+///
+/// On a cache miss the manager loads the token from the store before authenticating,
+/// and saves every token it obtains. Only tokens are stored, as a `reqwest::Client` can't be
+/// serialized, every manager builds its own client around a restored token. Tokens are not tied
+/// to a secret, so a token restored after the secret was rotated is used until it goes stale.
+#[async_trait]
+pub trait TokenStore: Send + Sync {
+    /// Returns the token stored for `api_key`, if any
+    ///
+    /// Unreadable entries should be treated as missing, expired ones are ignored by the manager.
+    async fn load(&self, auth: &AuthConfig, api_key: &str) -> Option<StoredToken>;
+
+    /// Stores `token` for `api_key`, replacing any previous one
+    async fn save(
+        &self,
+        auth: &AuthConfig,
+        api_key: &str,
+        token: &StoredToken,
+    ) -> anyhow::Result<()>;
+
+    /// Removes the token stored for `api_key`, called when its client is
+    /// [invalidated](crate::ClientManager::invalidate)
+    async fn remove(&self, auth: &AuthConfig, api_key: &str) -> anyhow::Result<()>;
+
+    /// Writes out any buffered tokens, called on [`shutdown`](crate::ClientManager::shutdown)
+    ///
+    /// Stores that persist every token right away can rely on the default, which does nothing.
+    async fn flush(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps tokens in memory, e.g. to share them between several managers of a process or in tests
+#[derive(Debug, Default)]
+pub struct MemoryTokenStore {
+    tokens: Mutex<HashMap<(AuthConfig, String), StoredToken>>,
+}
+
+impl MemoryTokenStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl TokenStore for MemoryTokenStore {
+    async fn load(&self, auth: &AuthConfig, api_key: &str) -> Option<StoredToken> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .get(&(auth.clone(), api_key.to_string()))
+            .cloned()
+    }
+
+    async fn save(
+        &self,
+        auth: &AuthConfig,
+        api_key: &str,
+        token: &StoredToken,
+    ) -> anyhow::Result<()> {
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.retain(|_, stored| stored.expires_at > token.issued_at);
+        tokens.insert((auth.clone(), api_key.to_string()), token.clone());
+
+        Ok(())
+    }
+
+    async fn remove(&self, auth: &AuthConfig, api_key: &str) -> anyhow::Result<()> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .remove(&(auth.clone(), api_key.to_string()));
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "persist")]
+pub use self::file::FileTokenStore;
+
+#[cfg(feature = "persist")]
+mod file {
+    use std::collections::HashMap;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+
+    use anyhow::Context;
+    use async_trait::async_trait;
+
+    use super::{StoredToken, TokenStore};
+    use crate::AuthConfig;
+
+    /// Stores tokens in a JSON file only readable by the current user
+    ///
+    /// The whole file is rewritten on every save, dropping expired tokens along the way.
+    /// A corrupted file is treated as empty. The file is small enough to be read and written
+    /// inline, blocking the calling task briefly.
+    #[derive(Debug)]
+    pub struct FileTokenStore {
+        path: PathBuf,
+        /// Serializes saves within the process
+        lock: Mutex<()>,
+    }
+
+    impl FileTokenStore {
+        pub fn new(path: impl Into<PathBuf>) -> Self {
+            Self {
+                path: path.into(),
+                lock: Mutex::new(()),
+            }
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+
+        fn read(&self) -> HashMap<String, StoredToken> {
+            fs::read(&self.path)
+                .ok()
+                .and_then(|contents| serde_json::from_slice(&contents).ok())
+                .unwrap_or_default()
+        }
+
+        fn write(&self, tokens: &HashMap<String, StoredToken>) -> anyhow::Result<()> {
+            let contents = serde_json::to_vec(tokens)?;
+
+            // Write to a temporary file first, so that a crash never leaves a truncated file behind
+            let tmp = self.path.with_extension("tmp");
+            // The permissions only apply when the file is created
+            let _ = fs::remove_file(&tmp);
+            let mut options = OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+            let mut file = options
+                .open(&tmp)
+                .with_context(|| format!("Failed to open {}", tmp.display()))?;
+            file.write_all(&contents)?;
+            file.sync_all()?;
+            fs::rename(&tmp, &self.path)
+                .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+
+            Ok(())
+        }
+    }
+
+    fn entry_key(auth: &AuthConfig, api_key: &str) -> String {
+        format!("{}/{}/{api_key}", auth.client_id, auth.pool_id)
+    }
+
+    #[async_trait]
+    impl TokenStore for FileTokenStore {
+        async fn load(&self, auth: &AuthConfig, api_key: &str) -> Option<StoredToken> {
+            self.read().remove(&entry_key(auth, api_key))
+        }
+
+        async fn save(
+            &self,
+            auth: &AuthConfig,
+            api_key: &str,
+            token: &StoredToken,
+        ) -> anyhow::Result<()> {
+            let _lock = self.lock.lock().unwrap();
+
+            let mut tokens = self.read();
+            tokens.retain(|_, stored| stored.expires_at > token.issued_at);
+            tokens.insert(entry_key(auth, api_key), token.clone());
+
+            self.write(&tokens)
+        }
+
+        async fn remove(&self, auth: &AuthConfig, api_key: &str) -> anyhow::Result<()> {
+            let _lock = self.lock.lock().unwrap();
+
+            let mut tokens = self.read();
+            if tokens.remove(&entry_key(auth, api_key)).is_none() {
+                return Ok(());
+            }
+
+            self.write(&tokens)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// A directory of its own under the system's temporary directory, removed on drop
+        struct TempDir(PathBuf);
+
+        impl TempDir {
+            fn new(name: &str) -> Self {
+                let path = std::env::temp_dir()
+                    .join(format!("file-token-store-{}-{name}", std::process::id()));
+                let _ = fs::remove_dir_all(&path);
+                fs::create_dir_all(&path).unwrap();
+
+                Self(path)
+            }
+        }
+
+        impl Drop for TempDir {
+            fn drop(&mut self) {
+                let _ = fs::remove_dir_all(&self.0);
+            }
+        }
+
+        fn token(access_token: &str) -> StoredToken {
+            StoredToken {
+                access_token: access_token.to_string(),
+                issued_at: 1_000,
+                expires_at: 4_600,
+            }
+        }
+
+        #[tokio::test]
+        async fn round_trip() {
+            let dir = TempDir::new("round-trip");
+            let store = FileTokenStore::new(dir.0.join("tokens.json"));
+            let auth = AuthConfig::default();
+            assert_eq!(store.load(&auth, "key").await, None);
+
+            store.save(&auth, "key", &token("first")).await.unwrap();
+            store.save(&auth, "other", &token("other")).await.unwrap();
+            store.save(&auth, "key", &token("second")).await.unwrap();
+            // Read back by another store, as after a restart
+            let store = FileTokenStore::new(store.path());
+            assert_eq!(store.load(&auth, "key").await, Some(token("second")));
+
+            store.remove(&auth, "key").await.unwrap();
+            assert_eq!(store.load(&auth, "key").await, None);
+            assert_eq!(store.load(&auth, "other").await, Some(token("other")));
+        }
+
+        #[tokio::test]
+        async fn tokens_are_kept_per_auth_config() {
+            let dir = TempDir::new("auth-configs");
+            let store = FileTokenStore::new(dir.0.join("tokens.json"));
+            let other = AuthConfig {
+                pool_id: "other".to_string(),
+                ..AuthConfig::default()
+            };
+
+            store
+                .save(&AuthConfig::default(), "key", &token("default"))
+                .await
+                .unwrap();
+
+            assert_eq!(store.load(&other, "key").await, None);
+        }
+
+        #[cfg(unix)]
+        #[tokio::test]
+        async fn file_is_only_readable_by_the_user() {
+            use std::os::unix::fs::PermissionsExt;
+
+            let dir = TempDir::new("mode");
+            let store = FileTokenStore::new(dir.0.join("tokens.json"));
+            store
+                .save(&AuthConfig::default(), "key", &token("token"))
+                .await
+                .unwrap();
+
+            let mode = fs::metadata(store.path()).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        #[tokio::test]
+        async fn corrupt_file_is_treated_as_empty() {
+            let dir = TempDir::new("corrupt");
+            let store = FileTokenStore::new(dir.0.join("tokens.json"));
+            let auth = AuthConfig::default();
+            fs::write(store.path(), b"{\"truncated").unwrap();
+
+            assert_eq!(store.load(&auth, "key").await, None);
+
+            // Replaced by the next save
+            store.save(&auth, "key", &token("token")).await.unwrap();
+            assert_eq!(store.load(&auth, "key").await, Some(token("token")));
+        }
+    }
+}
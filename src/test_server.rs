@@ -0,0 +1,124 @@
+//! A local HTTP server answering with scripted statuses, for tests of the replay on `401` and
+//! of the headers sent by the built clients
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+/// Answers every request with the next scripted status, `200 OK` once the script is exhausted,
+/// and records the headers of every request
+#[derive(Clone)]
+pub(crate) struct TestServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<HeaderMap>>>,
+    delay: Arc<Mutex<Duration>>,
+}
+
+impl TestServer {
+    pub async fn start(statuses: impl IntoIterator<Item = u16>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = Self {
+            url: format!("http://{}/", listener.local_addr().unwrap()),
+            requests: Arc::default(),
+            delay: Arc::default(),
+        };
+        let statuses = Arc::new(Mutex::new(statuses.into_iter().collect::<VecDeque<_>>()));
+
+        let handle = server.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(serve(
+                    BufReader::new(stream),
+                    statuses.clone(),
+                    handle.clone(),
+                ));
+            }
+        });
+
+        server
+    }
+
+    /// Waits this long before answering each request, e.g. to run into a client's timeout
+    pub fn set_delay(&self, delay: Duration) {
+        *self.delay.lock().unwrap() = delay;
+    }
+
+    /// The headers of the requests so far, in order
+    pub fn requests(&self) -> Vec<HeaderMap> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// The `Authorization` headers of the requests so far, in order, empty if one had none
+    pub fn authorizations(&self) -> Vec<String> {
+        self.requests()
+            .iter()
+            .map(|headers| {
+                headers
+                    .get(AUTHORIZATION)
+                    .map(|value| value.to_str().unwrap().to_string())
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+}
+
+async fn serve(
+    mut stream: BufReader<tokio::net::TcpStream>,
+    statuses: Arc<Mutex<VecDeque<u16>>>,
+    server: TestServer,
+) {
+    let mut line = String::new();
+    let (mut headers, mut content_length) = (HeaderMap::new(), 0);
+    let mut request_line = true;
+    loop {
+        line.clear();
+        if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+            return;
+        }
+
+        if std::mem::take(&mut request_line) {
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let name = HeaderName::from_bytes(name.trim().as_bytes()).unwrap();
+            let value = HeaderValue::from_str(value.trim()).unwrap();
+            if name == reqwest::header::CONTENT_LENGTH {
+                content_length = value.to_str().unwrap().parse().unwrap();
+            }
+            headers.append(name, value);
+        }
+
+        // The blank line ending the headers
+        if line == "\r\n" {
+            let mut body = vec![0; content_length];
+            stream.read_exact(&mut body).await.unwrap();
+
+            server
+                .requests
+                .lock()
+                .unwrap()
+                .push(std::mem::take(&mut headers));
+            let delay = *server.delay.lock().unwrap();
+            tokio::time::sleep(delay).await;
+
+            let status = statuses.lock().unwrap().pop_front().unwrap_or(200);
+            let response = format!("HTTP/1.1 {status} Scripted\r\ncontent-length: 0\r\n\r\n");
+            if stream
+                .get_mut()
+                .write_all(response.as_bytes())
+                .await
+                .is_err()
+            {
+                // The client gave up waiting
+                return;
+            }
+
+            (content_length, request_line) = (0, true);
+        }
+    }
+}
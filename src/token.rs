@@ -0,0 +1,54 @@
+use std::fmt;
+use std::sync::Arc;
+
+/// A bearer token together with the unix timestamp at which it expires
+///
+/// Cheap to clone. The token itself is redacted from the `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AccessToken {
+    token: Arc<str>,
+    expires_at: i64,
+}
+
+impl AccessToken {
+    pub(crate) fn new(token: &str, expires_at: i64) -> Self {
+        Self {
+            token: token.into(),
+            expires_at,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.token
+    }
+
+    /// Unix timestamp in seconds at which the token expires
+    pub fn expires_at(&self) -> i64 {
+        self.expires_at
+    }
+}
+
+impl fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessToken")
+            .field("token", &"<redacted>")
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_redacts_the_token() {
+        let token = AccessToken::new("s3cr3t-access-token", 4_600);
+
+        let debug = format!("{token:?}");
+        assert!(!debug.contains("s3cr3t-access-token"));
+        assert!(debug.contains("4600"));
+        assert!(!format!("{token:#?}").contains("s3cr3t-access-token"));
+        assert!(!format!("{:?}", Some(&token)).contains("s3cr3t-access-token"));
+    }
+}